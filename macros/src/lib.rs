@@ -0,0 +1,264 @@
+//! Procedural macros for the `companion-service` crate. These are re-exported
+//! by it and should be used through it.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Expr, ExprLit, Fields, ItemFn, ItemStruct, Lit, LitBool,
+    LitStr,
+};
+
+/// Turns a function into a test that needs the given services. See the
+/// `companion-service` documentation.
+#[proc_macro_attribute]
+pub fn companion_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut services = Vec::new();
+    let mut reset = false;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("services") {
+            let list: Expr = meta.value()?.parse()?;
+            services = service_names(&list)?;
+            Ok(())
+        } else if meta.path.is_ident("reset") {
+            reset = flag(&meta)?;
+            Ok(())
+        } else {
+            Err(meta.error("expected `services` or `reset`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = parse_macro_input!(item as ItemFn);
+    let stmts = &block.stmts;
+
+    quote! {
+        #[test]
+        #(#attrs)*
+        #vis #sig {
+            let _companion_services = ::companion_service::__private::prepare(
+                &[#(#services),*],
+                #reset,
+            );
+            #(#stmts)*
+        }
+    }
+    .into()
+}
+
+/// Parses an array of service names or tags, which follow the same rules.
+fn service_names(list: &Expr) -> syn::Result<Vec<LitStr>> {
+    let elements = match list {
+        Expr::Array(array) => &array.elems,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                list,
+                "expected an array of service names",
+            ))
+        }
+    };
+
+    elements
+        .iter()
+        .map(|element| match element {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(name),
+                ..
+            }) => validate_name(name).map(|()| name.clone()),
+            _ => {
+                Err(syn::Error::new_spanned(element, "expected a service name"))
+            }
+        })
+        .collect()
+}
+
+/// Implements `Service` for a struct and registers it in `SERVICES`. See the
+/// `companion-service` documentation.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name = None;
+    let mut depends_on = Vec::new();
+    let mut tags = Vec::new();
+    let mut lazy = false;
+    let mut multi_instance = false;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            let value: LitStr = meta.value()?.parse()?;
+            validate_name(&value)?;
+            name = Some(value);
+            Ok(())
+        } else if meta.path.is_ident("depends_on") {
+            let list: Expr = meta.value()?.parse()?;
+            depends_on = service_names(&list)?;
+            Ok(())
+        } else if meta.path.is_ident("tags") {
+            let list: Expr = meta.value()?.parse()?;
+            tags = service_names(&list)?;
+            Ok(())
+        } else if meta.path.is_ident("lazy") {
+            lazy = flag(&meta)?;
+            Ok(())
+        } else if meta.path.is_ident("multi_instance") {
+            multi_instance = flag(&meta)?;
+            Ok(())
+        } else {
+            Err(meta.error(
+                "expected `name`, `depends_on`, `tags`, `lazy` or \
+                 `multi_instance`",
+            ))
+        }
+    });
+    parse_macro_input!(attr with parser);
+
+    let item = parse_macro_input!(item as ItemStruct);
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return syn::Error::new_spanned(
+                &item.ident,
+                "missing service name, add `name = \"...\"`",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+    if !item.generics.params.is_empty() {
+        return syn::Error::new_spanned(
+            &item.generics,
+            "services cannot be generic",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let ident = &item.ident;
+    let registration = format_ident!(
+        "__COMPANION_SERVICE_{}",
+        ident.to_string().to_uppercase()
+    );
+    // Unit structs can be registered directly, others are constructed on
+    // first use
+    let (instance, storage) = match item.fields {
+        Fields::Unit => (quote! { &#ident }, quote! {}),
+        _ => {
+            let storage = format_ident!("{}_INSTANCE", registration);
+            (
+                quote! { &#storage },
+                quote! {
+                    static #storage: ::companion_service::LazyService<#ident> =
+                        ::companion_service::LazyService::new(
+                            <#ident as ::core::default::Default>::default,
+                        );
+                },
+            )
+        }
+    };
+
+    quote! {
+        #item
+
+        impl ::companion_service::Service for #ident {
+            fn name(&self) -> &str {
+                #name
+            }
+
+            fn depends_on(&self) -> &[&str] {
+                &[#(#depends_on),*]
+            }
+
+            fn tags(&self) -> &[&str] {
+                &[#(#tags),*]
+            }
+
+            fn lazy(&self) -> bool {
+                #lazy
+            }
+
+            fn location(
+                &self,
+            ) -> ::core::option::Option<::companion_service::Location> {
+                ::core::option::Option::Some(
+                    ::companion_service::Location::new(
+                        ::core::file!(),
+                        ::core::line!(),
+                    ),
+                )
+            }
+
+            fn multi_instance(&self) -> bool {
+                #multi_instance
+            }
+
+            fn start(
+                &self,
+            ) -> ::core::result::Result<(), ::companion_service::ServiceError>
+            {
+                // Without an inherent `start`, the call below would resolve
+                // to this very method. The helper trait makes it ambiguous
+                // instead, which fails to compile
+                #[allow(dead_code)]
+                trait RequiresInherentStart {
+                    fn start(&self) {}
+                }
+                impl RequiresInherentStart for #ident {}
+
+                #ident::start(self)
+            }
+
+            fn stop(
+                &self,
+            ) -> ::core::result::Result<(), ::companion_service::ServiceError>
+            {
+                // See `start`
+                #[allow(dead_code)]
+                trait RequiresInherentStop {
+                    fn stop(&self) {}
+                }
+                impl RequiresInherentStop for #ident {}
+
+                #ident::stop(self)
+            }
+        }
+
+        #storage
+
+        #[::companion_service::__private::linkme::distributed_slice(
+            ::companion_service::SERVICES
+        )]
+        #[linkme(crate = ::companion_service::__private::linkme)]
+        static #registration: &(dyn ::companion_service::Service + Sync) =
+            #instance;
+    }
+    .into()
+}
+
+/// Parses a flag that is either bare or set to a boolean literal.
+fn flag(meta: &syn::meta::ParseNestedMeta<'_>) -> syn::Result<bool> {
+    match meta.value() {
+        Ok(value) => Ok(value.parse::<LitBool>()?.value),
+        Err(_) => Ok(true),
+    }
+}
+
+/// Rejects names that cannot be told apart in logs and environment
+/// variables.
+fn validate_name(name: &LitStr) -> syn::Result<()> {
+    let value = name.value();
+    if value.is_empty() {
+        Err(syn::Error::new_spanned(
+            name,
+            "service names cannot be empty",
+        ))
+    } else if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Err(syn::Error::new_spanned(
+            name,
+            "service names cannot contain whitespace or control characters",
+        ))
+    } else {
+        Ok(())
+    }
+}
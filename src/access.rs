@@ -0,0 +1,68 @@
+//! Coordination between tests that use the same service.
+
+use crate::registry;
+use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+/// Guard returned by [`exclusive`]. Other tests are blocked from acquiring
+/// access to the service until it is dropped.
+#[must_use = "access is released as soon as the guard is dropped"]
+pub struct Exclusive {
+    _guards: Vec<RwLockWriteGuard<'static, ()>>,
+}
+
+/// Guard returned by [`shared`]. Tests asking for [`exclusive`] access to the
+/// service are blocked until it is dropped.
+#[must_use = "access is released as soon as the guard is dropped"]
+pub struct Shared {
+    _guards: Vec<RwLockReadGuard<'static, ()>>,
+}
+
+/// Acquires exclusive access to all services with the given name, blocking
+/// until every other [`Exclusive`] and [`Shared`] guard for them is dropped.
+/// Returns `None` if there is no such service.
+///
+/// This is meant for tests that restart or otherwise disturb a service that
+/// other tests use concurrently. Those other tests should hold a [`shared`]
+/// guard while they use the service. Lifecycle functions such as [`restart`]
+/// are not affected by these guards, and are always serialized per service.
+///
+/// Acquiring exclusive access while holding a guard for the same service on
+/// the same thread deadlocks.
+///
+/// [`restart`]: crate::restart
+pub fn exclusive(name: &str) -> Option<Exclusive> {
+    let guards: Vec<_> = registry::named(name)
+        .map(|entry| {
+            entry
+                .access()
+                .write()
+                .unwrap_or_else(|error| error.into_inner())
+        })
+        .collect();
+
+    if guards.is_empty() {
+        None
+    } else {
+        Some(Exclusive { _guards: guards })
+    }
+}
+
+/// Acquires shared access to all services with the given name, blocking
+/// while an [`Exclusive`] guard for any of them exists. Returns `None` if
+/// there is no such service.
+pub fn shared(name: &str) -> Option<Shared> {
+    let guards: Vec<_> = registry::named(name)
+        .map(|entry| {
+            entry
+                .access()
+                .read()
+                .unwrap_or_else(|error| error.into_inner())
+        })
+        .collect();
+
+    if guards.is_empty() {
+        None
+    } else {
+        Some(Shared { _guards: guards })
+    }
+}
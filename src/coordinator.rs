@@ -0,0 +1,483 @@
+//! Coordination of test processes under process-per-test runners such as
+//! cargo-nextest.
+//!
+//! The first test process of a test binary spawns a copy of the binary as a
+//! coordinator, in its own session so that it survives the test process. The
+//! coordinator starts all services, writes the PIDs of the running ones to a
+//! `ready` file and then waits until the test runner exits and no test
+//! process uses the services anymore, at which point it stops them. Test
+//! processes attach to the services listed in the `ready` file instead of
+//! starting them, and detach from them when they exit. If any service fails
+//! to start, the coordinator stops the others again and test processes exit
+//! like the automatic startup does.
+//!
+//! Test processes cannot restart or reset the services themselves without
+//! starting a second instance. They write a file to the `requests` directory
+//! instead, which the coordinator answers in the `responses` directory with
+//! the new PID of the service, or an error.
+
+use crate::{
+    janitor, registry, report, report_string, shared, shutdown,
+    startup_or_rollback, state_dir, warn, ServiceError, ServiceState,
+    STARTUP_FAILURE_EXIT_CODE,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Environment variable that forces the coordinator on (`1`) or off (`0`).
+const COORDINATOR_VAR: &str = "COMPANION_SERVICES_COORDINATOR";
+/// Environment variable set on the coordinator process itself.
+const ROLE_VAR: &str = "COMPANION_SERVICES_ROLE";
+/// Environment variable holding the directory of the coordinator.
+const DIR_VAR: &str = "COMPANION_SERVICES_COORDINATOR_DIR";
+/// Environment variable holding the PID of the test runner.
+const RUNNER_VAR: &str = "COMPANION_SERVICES_RUNNER";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Directory of the coordinator the current process attached to.
+static ATTACHED: OnceLock<PathBuf> = OnceLock::new();
+
+/// Lifecycle operation a test process asks the coordinator to carry out.
+#[derive(Clone, Copy)]
+pub(crate) enum Request {
+    Restart,
+    Reset,
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Request::Restart => "restart",
+            Request::Reset => "reset",
+        })
+    }
+}
+
+/// Has the coordinator carry out the request on the service with the given
+/// index, returning the PID of the service afterwards.
+pub(crate) fn forward(
+    request: Request,
+    index: usize,
+) -> io::Result<Option<u32>> {
+    static NEXT_REQUEST: AtomicU32 = AtomicU32::new(0);
+
+    let dir = ATTACHED
+        .get()
+        .ok_or_else(|| io::Error::other("not attached to a coordinator"))?;
+    let name = format!(
+        "{}-{}",
+        std::process::id(),
+        NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+    );
+    let requests = dir.join("requests");
+    fs::create_dir_all(&requests)?;
+    write_atomically(&requests.join(&name), &format!("{}={}", request, index))?;
+
+    let coordinator = fs::read_to_string(dir.join("pid"))
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok());
+    let response = dir.join("responses").join(&name);
+    loop {
+        if let Ok(contents) = fs::read_to_string(&response) {
+            let _ = fs::remove_file(&response);
+            return match contents.split_once('=') {
+                Some(("ok", pid)) => Ok(pid.parse().ok()),
+                Some(("error", message)) => Err(io::Error::other(message)),
+                _ => Err(io::Error::other("malformed coordinator response")),
+            };
+        }
+        if !coordinator.is_some_and(|pid| janitor::is_alive(pid, None)) {
+            return Err(io::Error::other("the coordinator is gone"));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// PID of the coordinator's instance of the service with the given index.
+/// Fails if the coordinator is not running it.
+pub(crate) fn running_pid(index: usize) -> io::Result<Option<u32>> {
+    let dir = ATTACHED
+        .get()
+        .ok_or_else(|| io::Error::other("not attached to a coordinator"))?;
+    let ready = fs::read_to_string(dir.join("ready"))?;
+    ready
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(i, _)| i.parse() == Ok(index))
+        .map(|(_, pid)| pid.parse().ok())
+        .ok_or_else(|| io::Error::other("not running in the coordinator"))
+}
+
+/// The role of the current process in the service lifecycle.
+pub(crate) enum Role {
+    /// Starts and stops its own services.
+    Standalone,
+    /// Owns the services on behalf of test processes.
+    Coordinator(Coordinator),
+    /// Uses the services owned by a coordinator.
+    Attached(Coordinator),
+    /// Only lists tests, and needs no services.
+    Listing,
+}
+
+/// Determines the role of the current process from its environment.
+pub(crate) fn role() -> Role {
+    if !cfg!(unix) {
+        return Role::Standalone;
+    }
+
+    if env::var_os(ROLE_VAR).as_deref() == Some("coordinator".as_ref()) {
+        if let (Some(dir), Some(runner)) = (
+            env::var_os(DIR_VAR),
+            env::var(RUNNER_VAR).ok().and_then(|pid| pid.parse().ok()),
+        ) {
+            return Role::Coordinator(Coordinator {
+                dir: dir.into(),
+                runner,
+            });
+        }
+    }
+
+    let enabled = match env::var(COORDINATOR_VAR).as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => env::var_os("NEXTEST").as_deref() == Some("1".as_ref()),
+    };
+    if !enabled {
+        return Role::Standalone;
+    }
+    if env::args().any(|arg| arg == "--list") {
+        return Role::Listing;
+    }
+
+    let runner = parent_pid();
+    let run = env::var("NEXTEST_RUN_ID").unwrap_or_else(|_| runner.to_string());
+    let mut hasher = DefaultHasher::new();
+    env::current_exe().ok().hash(&mut hasher);
+    let dir = state_dir().join("coordinators").join(format!(
+        "{}-{:016x}",
+        run,
+        hasher.finish()
+    ));
+
+    Role::Attached(Coordinator { dir, runner })
+}
+
+/// A coordinator process, as seen from either side.
+pub(crate) struct Coordinator {
+    dir: PathBuf,
+    runner: u32,
+}
+
+impl Coordinator {
+    /// Runs the coordinator. Never returns.
+    pub(crate) fn run(self) -> ! {
+        let runner_start_time = janitor::start_time(self.runner);
+        // Test processes cannot start lazy services on behalf of the others
+        if let Err(error) = self.publish(startup_or_rollback(true)) {
+            warn(format_args!("failed to publish service state: {}", error));
+        }
+
+        let users = self.dir.join("users");
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            self.serve();
+            if janitor::is_alive(self.runner, runner_start_time) {
+                continue;
+            }
+
+            let mut live_users = 0;
+            for user in fs::read_dir(&users).into_iter().flatten().flatten() {
+                let pid =
+                    user.file_name().to_str().and_then(|pid| pid.parse().ok());
+                match pid {
+                    Some(pid) if janitor::is_alive(pid, None) => {
+                        live_users += 1
+                    }
+                    _ => {
+                        let _ = fs::remove_file(user.path());
+                    }
+                }
+            }
+            if live_users == 0 {
+                break;
+            }
+        }
+
+        if let Err(errors) = shutdown() {
+            report("shutdown", &errors);
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+        std::process::exit(0)
+    }
+
+    /// Attaches to the services of the coordinator, spawning it first if
+    /// needed. Exits like the automatic startup if the coordinator failed to
+    /// start the services.
+    pub(crate) fn attach(self) {
+        match self.try_attach() {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(STARTUP_FAILURE_EXIT_CODE),
+            Err(error) => warn(format_args!(
+                "failed to attach to the coordinator in {}: {}",
+                self.dir.display(),
+                error
+            )),
+        }
+    }
+
+    /// Detaches from the services of the coordinator, and stops the ones this
+    /// process started itself.
+    pub(crate) fn detach(self) {
+        let errors: Vec<_> = registry::entries()
+            .iter()
+            .filter_map(|entry| entry.stop().err())
+            .collect();
+        report("shutdown", &errors);
+
+        let _ = fs::remove_file(self.user_path());
+    }
+
+    /// Returns whether the coordinator started the services. If it did not,
+    /// it already stopped the ones that did start.
+    fn try_attach(&self) -> io::Result<bool> {
+        fs::create_dir_all(self.dir.join("users"))?;
+        let coordinator = {
+            let lock = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(self.dir.join("lock"))?;
+            shared::lock_file(&lock)?;
+
+            let pid_path = self.dir.join("pid");
+            let pid = fs::read_to_string(&pid_path)
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok())
+                .filter(|&pid| janitor::is_alive(pid, None));
+            let pid = match pid {
+                Some(pid) => pid,
+                None => {
+                    let pid = self.spawn()?;
+                    fs::write(&pid_path, pid.to_string())?;
+                    pid
+                }
+            };
+            // Registered while holding the lock so the coordinator cannot
+            // miss this process
+            File::create(self.user_path())?;
+
+            pid
+        };
+
+        let ready = self.dir.join("ready");
+        while !ready.exists() {
+            if !janitor::is_alive(coordinator, None) {
+                return Err(io::Error::other(format!(
+                    "the coordinator exited early, see {}",
+                    self.log_path().display()
+                )));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        if let Ok(errors) = fs::read_to_string(self.dir.join("errors")) {
+            eprint!("{}", errors);
+            return Ok(false);
+        }
+
+        let _ = ATTACHED.set(self.dir.clone());
+        let entries = registry::entries();
+        let mut errors = Vec::new();
+        for line in fs::read_to_string(ready)?.lines() {
+            let (index, pid) = line.split_once('=').unwrap_or((line, ""));
+            if let Some((i, entry)) = index
+                .parse()
+                .ok()
+                .and_then(|i: usize| Some((i, entries.get(i)?)))
+            {
+                errors.extend(entry.attach(i, pid.parse().ok()).err());
+            }
+        }
+        report("startup", &errors);
+
+        Ok(true)
+    }
+
+    /// Writes the result of starting the services for test processes.
+    fn publish(&self, result: Result<(), Vec<ServiceError>>) -> io::Result<()> {
+        if let Err(errors) = result {
+            report("startup", &errors);
+            fs::write(
+                self.dir.join("errors"),
+                report_string("startup", &errors),
+            )?;
+        }
+
+        self.write_ready()
+    }
+
+    /// Lists the running services along with their PIDs.
+    fn write_ready(&self) -> io::Result<()> {
+        let mut ready = String::new();
+        for (i, entry) in registry::entries().iter().enumerate() {
+            if entry.state() == ServiceState::Running {
+                ready.push_str(&i.to_string());
+                ready.push('=');
+                if let Some(pid) = entry.service().pid() {
+                    ready.push_str(&pid.to_string());
+                }
+                ready.push('\n');
+            }
+        }
+
+        write_atomically(&self.dir.join("ready"), &ready)
+    }
+
+    /// Carries out the requests of test processes.
+    fn serve(&self) {
+        let requests = match fs::read_dir(self.dir.join("requests")) {
+            Ok(requests) => requests,
+            Err(_) => return,
+        };
+
+        let mut served = false;
+        for request in requests.flatten() {
+            let path = request.path();
+            // Still being written
+            if path.extension().is_some() {
+                continue;
+            }
+            let contents = fs::read_to_string(&path).unwrap_or_default();
+            let _ = fs::remove_file(&path);
+
+            let response = match handle(&contents) {
+                Ok(pid) => format!(
+                    "ok={}",
+                    pid.map(|pid| pid.to_string()).unwrap_or_default()
+                ),
+                Err(error) => format!("error={}", error),
+            };
+            let responses = self.dir.join("responses");
+            let result = fs::create_dir_all(&responses).and_then(|()| {
+                write_atomically(
+                    &responses.join(request.file_name()),
+                    &response,
+                )
+            });
+            if let Err(error) = result {
+                warn(format_args!("failed to answer a request: {}", error));
+            }
+            served = true;
+        }
+
+        // The PIDs of restarted services changed
+        if served {
+            if let Err(error) = self.write_ready() {
+                warn(format_args!(
+                    "failed to publish service state: {}",
+                    error
+                ));
+            }
+        }
+    }
+
+    fn spawn(&self) -> io::Result<u32> {
+        let log = File::create(self.log_path())?;
+        let mut command = Command::new(env::current_exe()?);
+        command
+            .env(ROLE_VAR, "coordinator")
+            .env(DIR_VAR, &self.dir)
+            .env(RUNNER_VAR, self.runner.to_string())
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+        detach_session(&mut command);
+
+        // The coordinator is never waited on, it outlives this process
+        Ok(command.spawn()?.id())
+    }
+
+    fn user_path(&self) -> PathBuf {
+        self.dir.join("users").join(std::process::id().to_string())
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join("coordinator.log")
+    }
+}
+
+/// Carries out a request of a test process, returning the PID of the service
+/// afterwards or a description of what went wrong.
+fn handle(request: &str) -> Result<Option<u32>, String> {
+    let entry = request
+        .split_once('=')
+        .and_then(|(_, index)| index.parse().ok())
+        .and_then(|i: usize| registry::entries().get(i).copied())
+        .ok_or_else(|| format!("malformed request `{}`", request))?;
+    let result = if request.starts_with("restart=") {
+        entry.restart()
+    } else {
+        entry.reset()
+    };
+    // Only the source, since the test process adds the rest
+    result.map_err(|error| match error.source() {
+        Some(source) => source.to_string(),
+        None => error.to_string(),
+    })?;
+
+    Ok(entry.service().pid())
+}
+
+/// Writes the file under a temporary name first, so that other processes
+/// never see it partially written.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)
+}
+
+#[cfg(unix)]
+fn parent_pid() -> u32 {
+    unsafe { libc::getppid() as u32 }
+}
+
+#[cfg(not(unix))]
+fn parent_pid() -> u32 {
+    0
+}
+
+/// Runs the command in a new session, so it is not affected by signals sent
+/// to the process group of the test process.
+#[cfg(unix)]
+fn detach_session(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn detach_session(_: &mut Command) {}
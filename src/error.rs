@@ -0,0 +1,180 @@
+use std::{any::Any, error::Error, fmt};
+
+/// Boxed error type used as the source of a [`ServiceError`].
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// The lifecycle phase during which a [`ServiceError`] happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Starting the service.
+    Start,
+    /// Stopping the service.
+    Stop,
+    /// Restarting the service.
+    Restart,
+    /// Registering the service at runtime.
+    Register,
+    /// Unregistering the service at runtime.
+    Unregister,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Start => "start",
+            Phase::Stop => "stop",
+            Phase::Restart => "restart",
+            Phase::Register => "register",
+            Phase::Unregister => "unregister",
+        })
+    }
+}
+
+/// Error used as the source of a [`ServiceError`] when the dependencies of a
+/// service cannot be satisfied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DependencyError {
+    /// The service depends on a name that no registered service has.
+    Missing(String),
+    /// The service is part of a dependency cycle. The names of the services
+    /// in the cycle are listed in order, with the first name repeated at the
+    /// end.
+    Cycle(Vec<String>),
+    /// A dependency with the given name failed to start, so the service was
+    /// not started either.
+    Failed(String),
+    /// The service cannot be unregistered since the services with the given
+    /// names depend on it.
+    Required(Vec<String>),
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyError::Missing(name) => {
+                write!(f, "depends on unknown service `{}`", name)
+            }
+            DependencyError::Cycle(names) => {
+                write!(f, "dependency cycle: {}", names.join(" -> "))
+            }
+            DependencyError::Failed(name) => {
+                write!(f, "dependency `{}` failed", name)
+            }
+            DependencyError::Required(names) => {
+                write!(f, "still required by `{}`", names.join("`, `"))
+            }
+        }
+    }
+}
+
+impl Error for DependencyError {}
+
+/// Error used as the source of a [`ServiceError`] when no service has the
+/// requested name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownService;
+
+impl fmt::Display for UnknownService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("no such service")
+    }
+}
+
+impl Error for UnknownService {}
+
+/// Error used as the source of a [`ServiceError`] when a lifecycle hook of
+/// the service panicked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PanicError {
+    message: String,
+}
+
+impl PanicError {
+    /// Creates an error from the payload of a panic.
+    pub(crate) fn new(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic payload".to_string(),
+            },
+        };
+
+        Self { message }
+    }
+
+    /// The panic message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for PanicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "panicked: {}", self.message)
+    }
+}
+
+impl Error for PanicError {}
+
+/// Error returned when a lifecycle operation fails. It carries the name of
+/// the service, the [`Phase`] that failed and the underlying error.
+#[derive(Debug)]
+pub struct ServiceError {
+    name: String,
+    phase: Phase,
+    source: BoxError,
+}
+
+impl ServiceError {
+    /// Creates a new error for the service with the given name.
+    pub fn new(
+        name: impl Into<String>,
+        phase: Phase,
+        source: impl Into<BoxError>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            phase,
+            source: source.into(),
+        }
+    }
+
+    /// Shorthand for a [`Phase::Start`] error.
+    pub fn start(name: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Self::new(name, Phase::Start, source)
+    }
+
+    /// Shorthand for a [`Phase::Stop`] error.
+    pub fn stop(name: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Self::new(name, Phase::Stop, source)
+    }
+
+    /// Name of the service that failed.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Phase during which the service failed.
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to {} service `{}`: {}",
+            self.phase, self.name, self.source
+        )
+    }
+}
+
+impl Error for ServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
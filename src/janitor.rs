@@ -0,0 +1,380 @@
+//! Cleanup of service instances left behind by previous runs.
+//!
+//! Whenever a service that reports a PID is started, a record with its PID,
+//! the PID of the current process (its owner) and both start times is
+//! written to the state directory. The record is removed when the service is
+//! stopped. Records whose owner is gone belong to a run that crashed or was
+//! killed: the janitor kills the process if it is still alive and removes the
+//! service's data directories. Processes are only killed on platforms where
+//! their start time can be checked, since their PID may have been reused.
+//!
+//! The death signal set up by the spawner only reaches the service process
+//! itself, not the processes it spawned, such as the server started by a
+//! wrapper script. On Linux, the janitor also kills what is left of the
+//! process group of a service that led one.
+
+use crate::{warn, Service};
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable that overrides the state directory.
+const STATE_DIR_VAR: &str = "COMPANION_SERVICES_STATE_DIR";
+
+/// A service instance removed by the janitor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StaleInstance {
+    /// Name of the service.
+    pub name: String,
+    /// PID of the service process.
+    pub pid: u32,
+    /// Whether the process was still alive and had to be killed.
+    pub killed: bool,
+    /// Data directories that were removed.
+    pub data_dirs: Vec<PathBuf>,
+}
+
+/// Directory where the crate keeps state between runs. This is
+/// `companion-service` inside the cargo target directory of the current
+/// executable, unless overridden with the `COMPANION_SERVICES_STATE_DIR`
+/// environment variable.
+pub fn state_dir() -> PathBuf {
+    if let Some(dir) = env::var_os(STATE_DIR_VAR) {
+        return dir.into();
+    }
+
+    let target = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .or_else(|| {
+            // Test executables live in `target/<profile>/deps`
+            let exe = env::current_exe().ok()?;
+            exe.ancestors()
+                .find(|dir| dir.file_name() == Some("target".as_ref()))
+                .map(Path::to_path_buf)
+        })
+        .unwrap_or_else(env::temp_dir);
+
+    target.join("companion-service")
+}
+
+/// Kills service processes left behind by previous runs and removes their
+/// data directories. This runs automatically before services are started.
+pub fn clean_stale_instances() -> io::Result<Vec<StaleInstance>> {
+    let dir = instances_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(error) => return Err(error),
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let record = match Record::read(&path) {
+            Some(record) => record,
+            // Most likely being written right now
+            None => continue,
+        };
+        if is_alive(record.owner, record.owner_start_time) {
+            continue;
+        }
+
+        let mut killed = is_recorded_process(record.pid, record.start_time);
+        if killed {
+            kill(record.pid);
+        } else if is_alive(record.pid, record.start_time) {
+            warn(format_args!(
+                "not killing process {} of stale service `{}` since it may \
+                 have reused the PID",
+                record.pid, record.name
+            ));
+        }
+        if let Some(group) = record.group {
+            killed |= kill_group_members(group, record.start_time);
+        }
+        for dir in &record.data_dirs {
+            match fs::remove_dir_all(dir) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    return Err(error)
+                }
+                _ => {}
+            }
+        }
+        remove_file(&path)?;
+
+        removed.push(StaleInstance {
+            name: record.name,
+            pid: record.pid,
+            killed,
+            data_dirs: record.data_dirs,
+        });
+    }
+
+    Ok(removed)
+}
+
+/// Records that the service was started by the current process. The ID tells
+/// apart instances of the same service.
+pub(crate) fn record(service: &dyn Service, id: u64) -> io::Result<()> {
+    let pid = match service.pid() {
+        Some(pid) => pid,
+        None => return Ok(()),
+    };
+
+    let owner = std::process::id();
+    let record = Record {
+        name: service.name().to_string(),
+        pid,
+        start_time: start_time(pid),
+        group: process_group(pid),
+        owner,
+        owner_start_time: start_time(owner),
+        data_dirs: service.data_dirs(),
+    };
+    fs::create_dir_all(instances_dir())?;
+    // Written to a temporary file first so that the janitor of a concurrent
+    // run never sees a partial record
+    let path = record_path(service.name(), id);
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, record.to_string())?;
+    fs::rename(temporary, path)
+}
+
+/// Forgets the record of the service, if any.
+pub(crate) fn forget(service: &dyn Service, id: u64) -> io::Result<()> {
+    remove_file(&record_path(service.name(), id))
+}
+
+fn instances_dir() -> PathBuf {
+    state_dir().join("instances")
+}
+
+fn record_path(name: &str, id: u64) -> PathBuf {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    instances_dir().join(format!(
+        "{}-{}-{}.state",
+        name,
+        std::process::id(),
+        id
+    ))
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+struct Record {
+    name: String,
+    pid: u32,
+    start_time: Option<u64>,
+    /// Process group led by the process, if any.
+    group: Option<u32>,
+    owner: u32,
+    owner_start_time: Option<u64>,
+    data_dirs: Vec<PathBuf>,
+}
+
+impl Record {
+    fn read(path: &Path) -> Option<Self> {
+        if path.extension() != Some("state".as_ref()) {
+            return None;
+        }
+
+        let contents = fs::read_to_string(path).ok()?;
+        let mut name = None;
+        let mut pid = None;
+        let mut start_time = None;
+        let mut group = None;
+        let mut owner = None;
+        let mut owner_start_time = None;
+        let mut data_dirs = Vec::new();
+        for line in contents.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "name" => name = Some(value.to_string()),
+                "pid" => pid = value.parse().ok(),
+                "start_time" => start_time = value.parse().ok(),
+                "group" => group = value.parse().ok(),
+                "owner" => owner = value.parse().ok(),
+                "owner_start_time" => owner_start_time = value.parse().ok(),
+                "data_dir" => data_dirs.push(value.into()),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            name: name?,
+            pid: pid?,
+            start_time,
+            group,
+            owner: owner?,
+            owner_start_time,
+            data_dirs,
+        })
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "name={}", self.name)?;
+        writeln!(f, "pid={}", self.pid)?;
+        if let Some(start_time) = self.start_time {
+            writeln!(f, "start_time={}", start_time)?;
+        }
+        if let Some(group) = self.group {
+            writeln!(f, "group={}", group)?;
+        }
+        writeln!(f, "owner={}", self.owner)?;
+        if let Some(start_time) = self.owner_start_time {
+            writeln!(f, "owner_start_time={}", start_time)?;
+        }
+        for dir in &self.data_dirs {
+            writeln!(f, "data_dir={}", dir.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Start time of a process, used to tell it apart from a later process that
+/// reused its PID.
+#[cfg(target_os = "linux")]
+pub(crate) fn start_time(pid: u32) -> Option<u64> {
+    proc_stat(pid)?.get(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn start_time(_: u32) -> Option<u64> {
+    None
+}
+
+/// Whether a process with the given PID and start time is running.
+#[cfg(target_os = "linux")]
+pub(crate) fn is_alive(pid: u32, expected_start_time: Option<u64>) -> bool {
+    let stat = match proc_stat(pid) {
+        Some(stat) => stat,
+        None => return false,
+    };
+    let zombie = stat.first().map(String::as_str) == Some("Z");
+    let reused = match expected_start_time {
+        Some(expected) => {
+            stat.get(19).and_then(|t| t.parse().ok()) != Some(expected)
+        }
+        None => false,
+    };
+
+    !zombie && !reused
+}
+
+/// The start time cannot be checked here, so a process that reused the PID
+/// is reported as alive.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn is_alive(pid: u32, _: Option<u64>) -> bool {
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to check, processes are assumed to be alive so that nothing
+/// is ever killed by mistake.
+#[cfg(not(unix))]
+pub(crate) fn is_alive(_: u32, _: Option<u64>) -> bool {
+    true
+}
+
+/// Whether the process is alive and known to be the one with the given start
+/// time, so that it can be killed without hitting a process that reused its
+/// PID.
+pub(crate) fn is_recorded_process(
+    pid: u32,
+    expected_start_time: Option<u64>,
+) -> bool {
+    expected_start_time.is_some() && is_alive(pid, expected_start_time)
+}
+
+/// Process group led by the process, if it leads one.
+#[cfg(unix)]
+fn process_group(pid: u32) -> Option<u32> {
+    let group = unsafe { libc::getpgid(pid as libc::pid_t) };
+    (group == pid as libc::pid_t).then_some(pid)
+}
+
+#[cfg(not(unix))]
+fn process_group(_: u32) -> Option<u32> {
+    None
+}
+
+/// Kills the live members of the process group, returning whether there were
+/// any. Only processes started no earlier than its leader are members, since
+/// the group ID may have been reused once the group was empty.
+#[cfg(target_os = "linux")]
+fn kill_group_members(group: u32, leader_start_time: Option<u64>) -> bool {
+    let leader_start_time = match leader_start_time {
+        Some(start_time) => start_time,
+        None => return false,
+    };
+
+    let mut killed = false;
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let pid =
+            match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+        let stat = match proc_stat(pid) {
+            Some(stat) => stat,
+            None => continue,
+        };
+        let field = |i: usize| stat.get(i).and_then(|field| field.parse().ok());
+        if stat.first().map(String::as_str) != Some("Z")
+            && field(2) == Some(u64::from(group))
+            && field(19)
+                .is_some_and(|start_time| start_time >= leader_start_time)
+        {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            killed = true;
+        }
+    }
+
+    killed
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kill_group_members(_: u32, _: Option<u64>) -> bool {
+    false
+}
+
+/// Fields of `/proc/<pid>/stat` after the command name, starting with the
+/// process state.
+#[cfg(target_os = "linux")]
+fn proc_stat(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name can contain spaces and parentheses
+    let (_, fields) = stat.rsplit_once(')')?;
+
+    Some(fields.split_whitespace().map(str::to_string).collect())
+}
+
+/// Kills the process along with its process group, if it leads one.
+#[cfg(unix)]
+pub(crate) fn kill(pid: u32) {
+    let pid = pid as libc::pid_t;
+    unsafe {
+        if libc::getpgid(pid) == pid {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+        libc::kill(pid, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill(_: u32) {}
@@ -0,0 +1,58 @@
+//! Explicit control over the lifecycle of all services.
+
+use crate::{
+    first_error, report, shutdown, startup, startup_or_rollback, ServiceError,
+};
+
+/// Guard returned by [`launch`]. Stops all services when dropped.
+#[must_use = "services are stopped as soon as the guard is dropped"]
+pub struct Companions {
+    stopped: bool,
+}
+
+impl Companions {
+    /// Stops all services, returning the first error encountered, if any.
+    /// Dropping the guard does the same but only prints errors.
+    pub fn stop(mut self) -> Result<(), ServiceError> {
+        self.stopped = true;
+        stop_all()
+    }
+}
+
+impl Drop for Companions {
+    fn drop(&mut self) {
+        if self.stopped {
+            return;
+        }
+
+        if let Err(errors) = shutdown() {
+            report("shutdown", &errors);
+        }
+    }
+}
+
+/// Starts all services like the automatic startup does, and returns a guard
+/// that stops them when dropped. If any service fails to start, the ones
+/// that did are stopped again and the first error is returned.
+///
+/// This is meant to be called from `main` or a test harness when the
+/// `manual` feature disables the automatic startup and shutdown.
+pub fn launch() -> Result<Companions, ServiceError> {
+    startup_or_rollback(false).map_err(first_error)?;
+
+    Ok(Companions { stopped: false })
+}
+
+/// Starts all services like the automatic startup does: stale instances are
+/// cleaned up first, and services are selected through the environment and
+/// started in dependency order. Services that fail do not prevent unrelated
+/// ones from starting. Returns the first error encountered, if any.
+pub fn start_all() -> Result<(), ServiceError> {
+    startup(false).map_err(first_error)
+}
+
+/// Stops all running services in reverse dependency order. Returns the first
+/// error encountered, if any.
+pub fn stop_all() -> Result<(), ServiceError> {
+    shutdown().map_err(first_error)
+}
@@ -0,0 +1,115 @@
+//! Lazily-initialized services.
+
+use crate::{
+    Exit, Location, Readiness, Service, ServiceError, Supervision, Timeouts,
+};
+use std::{ops::Deref, path::PathBuf, sync::OnceLock};
+
+/// A service that is constructed the first time it is used. This allows
+/// services without a `const` constructor, such as
+/// [`ProcessService`](crate::ProcessService), to be placed in a `static` and
+/// registered in [`SERVICES`](static@crate::SERVICES).
+pub struct LazyService<S> {
+    service: OnceLock<S>,
+    init: fn() -> S,
+}
+
+impl<S> LazyService<S> {
+    /// Creates a lazy service that will be constructed with `init`.
+    pub const fn new(init: fn() -> S) -> Self {
+        Self {
+            service: OnceLock::new(),
+            init,
+        }
+    }
+}
+
+impl<S> Deref for LazyService<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.service.get_or_init(self.init)
+    }
+}
+
+impl<S: Service> Service for LazyService<S> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        (**self).depends_on()
+    }
+
+    fn tags(&self) -> &[&str] {
+        (**self).tags()
+    }
+
+    fn readiness(&self) -> Readiness {
+        (**self).readiness()
+    }
+
+    fn pid(&self) -> Option<u32> {
+        (**self).pid()
+    }
+
+    fn data_dirs(&self) -> Vec<PathBuf> {
+        (**self).data_dirs()
+    }
+
+    fn location(&self) -> Option<Location> {
+        (**self).location()
+    }
+
+    fn multi_instance(&self) -> bool {
+        (**self).multi_instance()
+    }
+
+    fn shared_between_processes(&self) -> bool {
+        (**self).shared_between_processes()
+    }
+
+    fn lazy(&self) -> bool {
+        (**self).lazy()
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        (**self).timeouts()
+    }
+
+    fn kill(&self) -> bool {
+        (**self).kill()
+    }
+
+    fn supervision(&self) -> Supervision {
+        (**self).supervision()
+    }
+
+    fn exited(&self) -> Option<Exit> {
+        (**self).exited()
+    }
+
+    fn attach(&self, pid: Option<u32>) -> Result<(), ServiceError> {
+        (**self).attach(pid)
+    }
+
+    fn detach(&self) -> Result<(), ServiceError> {
+        (**self).detach()
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        (**self).start()
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        (**self).stop()
+    }
+
+    fn restart(&self) -> Result<(), ServiceError> {
+        (**self).restart()
+    }
+
+    fn reset(&self) -> Result<(), ServiceError> {
+        (**self).reset()
+    }
+}
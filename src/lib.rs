@@ -8,7 +8,7 @@
 //!
 //! ```rust
 //! use linkme::distributed_slice;
-//! use companion_service::{Service, SERVICES};
+//! use companion_service::{Service, ServiceError, SERVICES};
 //!
 //! struct Dummy;
 //!
@@ -17,21 +17,264 @@
 //!     "dummy"
 //!   }
 //!
-//!   fn start(&self) {
+//!   fn start(&self) -> Result<(), ServiceError> {
 //!     print!("start!");
+//!     Ok(())
 //!   }
 //!
-//!   fn stop(&self) {
+//!   fn stop(&self) -> Result<(), ServiceError> {
 //!     print!("stop!");
+//!     Ok(())
 //!   }
 //! }
 //!
 //! #[distributed_slice(SERVICES)]
 //! static DUMMY: &(dyn Service + Sync) = &Dummy;
 //! ```
+//!
+//! The [`service`] attribute generates the [`Service`] implementation and the
+//! registration from inherent `start` and `stop` methods instead. Structs
+//! with fields must implement [`Default`], and are constructed on first use:
+//!
+//! ```rust
+//! use companion_service::{service, ServiceError};
+//!
+//! #[service(name = "broker", tags = ["queue"], lazy)]
+//! struct Broker;
+//!
+//! impl Broker {
+//!   fn start(&self) -> Result<(), ServiceError> {
+//!     Ok(())
+//!   }
+//!
+//!   fn stop(&self) -> Result<(), ServiceError> {
+//!     Ok(())
+//!   }
+//! }
+//! ```
+//!
+//! Both methods are required, and leaving one out fails to compile rather
+//! than calling the [`Service`] method of the same name:
+//!
+//! ```compile_fail,E0034
+//! use companion_service::{service, ServiceError};
+//!
+//! #[service(name = "oops")]
+//! struct Oops;
+//!
+//! impl Oops {
+//!   fn stop(&self) -> Result<(), ServiceError> {
+//!     Ok(())
+//!   }
+//! }
+//! ```
+//!
+//! Services can also be registered at runtime with [`register`], for
+//! example when they are built from configuration or when the linker does
+//! not support [`linkme`]. They are handled like the ones in [`SERVICES`]
+//! from then on, and can be removed again with [`unregister`].
+//!
+//! Service names cannot be empty or contain whitespace or control
+//! characters, and must be unique. The [`service`] attribute checks the
+//! former at compile time, and all services are checked before anything is
+//! started. Services that intentionally share a name must all opt into
+//! [`Service::multi_instance`].
+//!
+//! With the `manual` feature, nothing is started before `main` or stopped
+//! after it. The lifecycle is instead driven explicitly, either through
+//! [`start_all`] and [`stop_all`] or through the guard returned by
+//! [`launch`]. Those work without the feature too.
+//!
+//! Lifecycle operations are fallible and report failures as a
+//! [`ServiceError`]. Panics in [`Service`] methods are caught and reported
+//! the same way, with a [`PanicError`] as the source, so that a single
+//! service cannot prevent the others from being started or stopped.
+//! Failures during the automatic shutdown are printed to stderr. The
+//! automatic startup is all or nothing: if any service fails to start, the
+//! ones that did are stopped again in reverse dependency order, every error
+//! is printed to stderr, and the process exits with
+//! [`STARTUP_FAILURE_EXIT_CODE`] before `main` runs.
+//!
+//! Services can depend on each other by name through
+//! [`Service::depends_on`]. Services are started in dependency order before
+//! `main` and stopped in the reverse order after it.
+//!
+//! A service can also describe how to tell when it is ready to accept work
+//! through [`Service::readiness`]. Startup only moves on to the next service,
+//! and the toplevel [`start`] and [`restart`] functions only return, once all
+//! of its [`Probe`]s pass.
+//!
+//! For the common case of running an external program, the crate provides
+//! [`ProcessService`], which can be registered through a [`LazyService`].
+//!
+//! Services that report a PID through [`Service::pid`] are recorded in the
+//! [`state_dir`] while running. If a run crashes before stopping them, they
+//! are killed and their [`Service::data_dirs`] removed before the next run
+//! starts its services. See [`clean_stale_instances`].
+//!
+//! The crate tracks the [`ServiceState`] of every service, so starting a
+//! running service or stopping a stopped one does nothing. The current state
+//! can be queried with [`status`] and [`statuses`].
+//!
+//! Tests that need a service only for their own duration can use [`scoped`],
+//! which returns a guard that starts the service and stops it again when
+//! dropped. Guards for the same service are reference counted.
+//!
+//! Tests can declare the services they need with the [`companion_test`]
+//! attribute, which replaces `#[test]`:
+//!
+//! ```rust
+//! use companion_service::companion_test;
+//!
+//! #[companion_test(services = ["db", "cache"], reset)]
+//! fn inserts_a_row() {
+//!     // "db" and "cache" are running and ready here
+//! }
+//! ```
+//!
+//! The services are started with [`ensure`] if needed, and the test fails if
+//! any of them is unavailable. The test holds [`shared`] access to them while
+//! it runs. With `reset`, it holds [`exclusive`] access instead, and the
+//! services are [`reset`](Service::reset) after it.
+//!
+//! Lifecycle operations on the same service are serialized, so tests running
+//! in parallel can safely call [`restart`] on a shared service. Tests that
+//! need a service to themselves while they disturb it can use [`exclusive`],
+//! with the tests that merely use it holding a [`shared`] guard.
+//!
+//! Since `cargo test` runs one binary per integration test file, services can
+//! opt into being shared between processes through
+//! [`Service::shared_between_processes`]. A single instance is then started
+//! for all binaries, and stopped when the last one exits.
+//!
+//! Which services are started automatically can be controlled through
+//! environment variables. `COMPANION_SERVICES` and `COMPANION_SERVICES_SKIP`
+//! take comma-separated lists of service names, where `*` and `?` act as
+//! wildcards. Patterns such as `tag:database` match the [tags] of services
+//! instead of their names. When the former is set, only matching services
+//! and their dependencies are started. Services matching the latter are
+//! never started, even as dependencies. Setting `COMPANION_SERVICES_DISABLE`
+//! to anything but `0` disables the automatic startup altogether. Skipped
+//! services can still be started explicitly with [`start`].
+//!
+//! [tags]: Service::tags
+//!
+//! Tags also allow controlling related services together through
+//! [`start_group`], [`stop_group`] and [`restart_group`].
+//!
+//! Services are started concurrently before `main`, each as soon as its
+//! dependencies are ready. Setting `COMPANION_SERVICES_SEQUENTIAL` to
+//! anything but `0` starts them one at a time instead, which can help when
+//! debugging. Setting `COMPANION_SERVICES_TIMINGS` likewise prints how long
+//! each one took to start, which is also available through [`Status`].
+//!
+//! Starting, stopping and restarting a service can be limited in time
+//! through [`Service::timeouts`], or for all services by setting
+//! `COMPANION_SERVICES_TIMEOUT` to a number of seconds. Each phase can also
+//! be limited separately through `COMPANION_SERVICES_START_TIMEOUT`,
+//! `COMPANION_SERVICES_STOP_TIMEOUT` and `COMPANION_SERVICES_RESTART_TIMEOUT`.
+//! A watchdog thread reports services that take longer and
+//! [kills](Service::kill) them, which can be turned off by setting
+//! `COMPANION_SERVICES_TIMEOUT_KILL` to `0`. If the service is still stuck
+//! afterwards, the process exits immediately with [`TIMEOUT_EXIT_CODE`]
+//! rather than hanging forever, such as in a `cargo test` run whose services
+//! do not stop.
+//!
+//! Running services are also supervised by a background thread, which
+//! notices when they exit on their own through [`Service::exited`], as
+//! [`ProcessService`] does when its process dies. Such services are stopped
+//! to clean up after them and restarted according to their
+//! [`Service::supervision`], with an exponential backoff between attempts.
+//! Services that are not restarted are marked as
+//! [`Failed`](ServiceState::Failed) instead of appearing to run, unless they
+//! exited successfully and their policy does not ask for a restart. How many
+//! times each one exited is available through [`Status`].
+//!
+//! Services marked [`lazy`](Service::lazy) are not started before `main`,
+//! but the first time a test calls [`ensure`] with their name. This keeps
+//! tests that need no services fast. Lazy services that were started are
+//! stopped after `main` like any other.
+//!
+//! Under [cargo-nextest](https://nexte.st), every test runs in its own
+//! process. To avoid starting and stopping every service once per test, the
+//! first test process of each binary spawns a coordinator process that owns
+//! the services for the whole run, and test processes merely attach to them.
+//! This is detected through the `NEXTEST` environment variable, and can be
+//! forced on or off by setting `COMPANION_SERVICES_COORDINATOR` to `1` or `0`.
+//! Stopping a service from a test process only detaches from it, and
+//! starting it again attaches to it again, since other test processes may
+//! still be using it. Restarting or resetting it is carried out by the
+//! coordinator, on behalf of all test processes. The coordinator starts lazy
+//! services along with the others, since test processes cannot start them on
+//! its behalf. If any service fails to start, the coordinator stops the
+//! others again, and test processes exit with [`STARTUP_FAILURE_EXIT_CODE`].
 
+#[cfg(not(feature = "manual"))]
 use ctor::{ctor, dtor};
 use linkme::distributed_slice;
+#[cfg(not(feature = "manual"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fmt::Display, path::PathBuf};
+
+mod access;
+#[cfg_attr(feature = "manual", allow(dead_code))]
+mod coordinator;
+mod error;
+mod janitor;
+mod launch;
+mod lazy;
+mod order;
+mod parallel;
+mod process;
+mod readiness;
+mod registry;
+mod scoped;
+mod selection;
+mod shared;
+mod spawner;
+mod status;
+mod supervisor;
+mod testing;
+mod validation;
+mod watchdog;
+
+pub use crate::{
+    access::{exclusive, shared, Exclusive, Shared},
+    error::{
+        BoxError, DependencyError, PanicError, Phase, ServiceError,
+        UnknownService,
+    },
+    janitor::{clean_stale_instances, state_dir, StaleInstance},
+    launch::{launch, start_all, stop_all, Companions},
+    lazy::LazyService,
+    process::{
+        Input, ProcessService, ProcessServiceBuilder, Signal, StopOutcome,
+    },
+    readiness::{Probe, Readiness, ReadinessError},
+    scoped::{scoped, Scoped},
+    status::{status, statuses, ServiceState, Status},
+    supervisor::{Exit, RestartPolicy, Supervision},
+    validation::{Location, RegistrationError},
+    watchdog::{Timeouts, TIMEOUT_EXIT_CODE},
+};
+pub use companion_service_macros::{companion_test, service};
+
+#[cfg(not(feature = "manual"))]
+use crate::coordinator::Role;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::testing::{prepare, TestServices};
+    pub use linkme;
+}
+
+/// Exit code of processes whose services failed to start before `main`.
+pub const STARTUP_FAILURE_EXIT_CODE: i32 = 3;
+
+/// Whether the automatic startup failed and was rolled back, in which case
+/// there is nothing left to stop after `main`.
+#[cfg(not(feature = "manual"))]
+static STARTUP_FAILED: AtomicBool = AtomicBool::new(false);
 
 /// The distributed slice handled by [`linkme`].
 #[distributed_slice]
@@ -47,61 +290,481 @@ pub trait Service {
     /// [`restart`].
     fn name(&self) -> &str;
 
+    /// Names of the services this service depends on. Dependencies are
+    /// started before and stopped after this service. Defaults to no
+    /// dependencies.
+    fn depends_on(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Tags of this service, such as `"database"` or `"slow"`. Services can
+    /// be controlled by tag through [`start_group`], [`stop_group`] and
+    /// [`restart_group`], and selected by tag through the environment.
+    /// Defaults to no tags.
+    fn tags(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Readiness probes for this service. These are polled after the service
+    /// is started or restarted, until they all pass or their timeout expires.
+    /// Defaults to no probes, meaning the service is ready as soon as
+    /// [`Service::start`] returns.
+    fn readiness(&self) -> Readiness {
+        Readiness::new()
+    }
+
+    /// PID of the process backing this service while it is running, if any.
+    /// Used to clean up after runs that did not stop their services. Defaults
+    /// to `None`.
+    fn pid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Directories holding data of this service that should be removed when
+    /// cleaning up after a run that did not stop it. Defaults to none.
+    fn data_dirs(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Where this service is registered, used to report invalid
+    /// registrations. Filled in by the [`service`] attribute. Defaults to
+    /// `None`.
+    fn location(&self) -> Option<Location> {
+        None
+    }
+
+    /// Whether this service is meant to share its name with other services,
+    /// so that lifecycle functions called with the name act on all of them.
+    /// Services sharing a name are rejected before `main` unless they all
+    /// opt into this. Defaults to `false`.
+    fn multi_instance(&self) -> bool {
+        false
+    }
+
+    /// Whether a single instance of this service is shared by all processes
+    /// using the same [`state_dir`], such as the test binaries of a single
+    /// `cargo test` run. The first process actually starts the service, later
+    /// processes [`attach`](Service::attach) to it, and the last one to stop
+    /// it actually stops it. Only supported on Unix. Defaults to `false`.
+    fn shared_between_processes(&self) -> bool {
+        false
+    }
+
+    /// Whether this service is only started when first needed, through
+    /// [`ensure`], instead of before `main`. It is still started before
+    /// `main` if a service that is not lazy depends on it. Not to be confused
+    /// with [`LazyService`], which is about constructing the service object.
+    /// Defaults to `false`.
+    fn lazy(&self) -> bool {
+        false
+    }
+
+    /// Timeouts for starting, stopping and restarting this service. An
+    /// operation that takes longer is reported, the service is
+    /// [killed](Service::kill) if possible, and the process exits with
+    /// [`TIMEOUT_EXIT_CODE`] if that does not unblock it. Defaults to the
+    /// global timeouts set through the environment, if any.
+    fn timeouts(&self) -> Timeouts {
+        Timeouts::new()
+    }
+
+    /// Forcefully kills whatever backs this service after one of its
+    /// [`timeouts`](Service::timeouts) expired. This is called from another
+    /// thread while the operation that timed out is still running, so it
+    /// must not block on it. Returns whether anything was killed. Defaults
+    /// to `false`.
+    fn kill(&self) -> bool {
+        false
+    }
+
+    /// Whether and how this service is restarted after exiting on its own,
+    /// as detected through [`Service::exited`]. Defaults to never restarting
+    /// it.
+    fn supervision(&self) -> Supervision {
+        Supervision::default()
+    }
+
+    /// Checks whether this service exited on its own while running, such as
+    /// a crashed process. Polled periodically while the service is running.
+    /// Services that exited are stopped to clean up after them, and then
+    /// restarted according to [`Service::supervision`]. Defaults to `None`,
+    /// meaning the service never exits on its own.
+    fn exited(&self) -> Option<Exit> {
+        None
+    }
+
+    /// Called instead of [`Service::start`] when the service is shared
+    /// between processes and another process already started it. `pid` is the
+    /// PID reported by that process through [`Service::pid`]. Implementors
+    /// should make [`Service::stop`] work afterwards, since this process may
+    /// be the last one using the service. Defaults to doing nothing.
+    fn attach(&self, pid: Option<u32>) -> Result<(), ServiceError> {
+        let _ = pid;
+        Ok(())
+    }
+
+    /// Called instead of [`Service::stop`] when the service is shared between
+    /// processes and other processes still use it. Must leave the service
+    /// running. Defaults to doing nothing.
+    fn detach(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
     /// Starts the service. This is called once before `main`, and also as a
     /// result of the toplevel [`start`] function being called with the name of
     /// this service.
-    fn start(&self);
+    fn start(&self) -> Result<(), ServiceError>;
 
     /// Stops the service. This is called once after `main`, and also as a
     /// result of the toplevel [`stop`] function being called with the name of
     /// this service.
-    fn stop(&self);
+    fn stop(&self) -> Result<(), ServiceError>;
 
     /// Restarts the service. This is called as a result of the toplevel
     /// [`restart`] function being called with the name of this service.
-    fn restart(&self) {
-        self.stop();
-        self.start();
+    fn restart(&self) -> Result<(), ServiceError> {
+        self.stop()?;
+        self.start()
+    }
+
+    /// Resets the state of the service, such as the contents of a database,
+    /// so that the next test starts from scratch. This is called as a result
+    /// of the toplevel [`reset`] function being called with the name of this
+    /// service, and by [`companion_test`] when asked to. Defaults to
+    /// restarting the service.
+    fn reset(&self) -> Result<(), ServiceError> {
+        self.restart()
     }
 }
 
-/// Starts all services with the given name.
-pub fn start(name: &str) {
-    for service in SERVICES {
-        if service.name() == name {
-            service.start();
-        }
+/// Registers a service at runtime, alongside the ones in
+/// [`SERVICES`](static@SERVICES). It is not started, but is otherwise
+/// handled like them from then on, including by [`launch`] and the automatic
+/// shutdown. Fails with a [`RegistrationError`] if the name is invalid or
+/// already taken, and with [`DependencyError::Missing`] if a dependency is not
+/// registered yet. See [`Service::multi_instance`].
+///
+/// Under cargo-nextest, the coordinator process does not know about services
+/// registered at runtime, so every test process starts its own instance.
+#[track_caller]
+pub fn register(
+    service: Box<dyn Service + Send + Sync>,
+) -> Result<(), ServiceError> {
+    let location = service
+        .location()
+        .or_else(|| Some(std::panic::Location::caller().into()));
+    registry::register(Box::leak(service), location)
+}
+
+/// Stops and unregisters all services with the given name, whether they were
+/// registered at runtime or in [`SERVICES`](static@SERVICES). Fails with
+/// [`UnknownService`] if there is no such service, and with
+/// [`DependencyError::Required`] if other services still depend on it.
+/// Services that fail to stop are not unregistered.
+pub fn unregister(name: &str) -> Result<(), ServiceError> {
+    let entries = registry::entries();
+    if !entries.iter().any(|entry| entry.service().name() == name) {
+        return Err(ServiceError::new(name, Phase::Unregister, UnknownService));
+    }
+
+    let mut dependents: Vec<_> = entries
+        .iter()
+        .map(|entry| entry.service())
+        .filter(|service| {
+            service.name() != name && service.depends_on().contains(&name)
+        })
+        .map(|service| service.name().to_string())
+        .collect();
+    if !dependents.is_empty() {
+        dependents.sort_unstable();
+        dependents.dedup();
+        return Err(ServiceError::new(
+            name,
+            Phase::Unregister,
+            DependencyError::Required(dependents),
+        ));
+    }
+
+    stop(name)?;
+    registry::unregister(name);
+
+    Ok(())
+}
+
+/// Starts all services with the given name. Services that are already
+/// running are left alone. Returns the first error encountered, if any.
+pub fn start(name: &str) -> Result<(), ServiceError> {
+    for entry in registry::named(name) {
+        entry.start()?;
+    }
+
+    Ok(())
+}
+
+/// Stops all services with the given name. Services that are already stopped
+/// are left alone. Returns the first error encountered, if any.
+pub fn stop(name: &str) -> Result<(), ServiceError> {
+    for entry in registry::named(name) {
+        entry.stop()?;
+    }
+
+    Ok(())
+}
+
+/// Restarts all services with the given name. Services that are stopped are
+/// simply started. Returns the first error encountered, if any.
+pub fn restart(name: &str) -> Result<(), ServiceError> {
+    for entry in registry::named(name) {
+        entry.restart()?;
+    }
+
+    Ok(())
+}
+
+/// Resets the state of all services with the given name and waits until they
+/// are ready again. Services that are not running are left alone. Returns the
+/// first error encountered, if any.
+pub fn reset(name: &str) -> Result<(), ServiceError> {
+    for entry in registry::named(name) {
+        entry.reset()?;
+    }
+
+    Ok(())
+}
+
+/// Starts all services with the given name, along with everything they
+/// transitively depend on, in dependency order. Returns the first error
+/// encountered, if any.
+pub fn start_with_dependencies(name: &str) -> Result<(), ServiceError> {
+    let entries = registry::entries();
+    let order = registry::graph(&entries, Phase::Start)
+        .and_then(|graph| graph.order_with_dependencies(name))
+        .map_err(first_error)?;
+    for i in order {
+        entries[i].start()?;
+    }
+
+    Ok(())
+}
+
+/// Makes sure all services with the given name are running, starting them
+/// along with everything they transitively depend on if needed. Returns once
+/// they are ready. Meant for [`lazy`](Service::lazy) services, which are not
+/// started before `main`. Fails with [`UnknownService`] if there is no such
+/// service.
+pub fn ensure(name: &str) -> Result<(), ServiceError> {
+    if registry::named(name).next().is_none() {
+        return Err(ServiceError::start(name, UnknownService));
+    }
+
+    start_with_dependencies(name)
+}
+
+/// Stops all services with the given name, along with everything that
+/// transitively depends on them, in reverse dependency order. Returns the
+/// first error encountered, if any.
+pub fn stop_with_dependents(name: &str) -> Result<(), ServiceError> {
+    let entries = registry::entries();
+    let order = registry::graph(&entries, Phase::Stop)
+        .and_then(|graph| graph.order_with_dependents(name))
+        .map_err(first_error)?;
+    for i in order.into_iter().rev() {
+        entries[i].stop()?;
+    }
+
+    Ok(())
+}
+
+/// Starts all services with the given tag, along with everything they
+/// transitively depend on, in dependency order. Returns the first error
+/// encountered, if any.
+pub fn start_group(tag: &str) -> Result<(), ServiceError> {
+    let entries = registry::entries();
+    let graph = registry::graph(&entries, Phase::Start).map_err(first_error)?;
+    let order = graph
+        .filtered_order(&graph.with_dependencies(tagged(&entries, tag)))
+        .map_err(first_error)?;
+    for i in order {
+        entries[i].start()?;
+    }
+
+    Ok(())
+}
+
+/// Stops all services with the given tag, along with everything that
+/// transitively depends on them, in reverse dependency order. Returns the
+/// first error encountered, if any.
+pub fn stop_group(tag: &str) -> Result<(), ServiceError> {
+    let entries = registry::entries();
+    let graph = registry::graph(&entries, Phase::Stop).map_err(first_error)?;
+    let order = graph
+        .filtered_order(&graph.with_dependents(tagged(&entries, tag)))
+        .map_err(first_error)?;
+    for i in order.into_iter().rev() {
+        entries[i].stop()?;
+    }
+
+    Ok(())
+}
+
+/// Restarts all services with the given tag in dependency order. Services
+/// that are stopped are simply started. Returns the first error encountered,
+/// if any.
+pub fn restart_group(tag: &str) -> Result<(), ServiceError> {
+    let entries = registry::entries();
+    let graph =
+        registry::graph(&entries, Phase::Restart).map_err(first_error)?;
+    let mut included = vec![false; entries.len()];
+    for i in tagged(&entries, tag) {
+        included[i] = true;
+    }
+    for i in graph.filtered_order(&included).map_err(first_error)? {
+        entries[i].restart()?;
+    }
+
+    Ok(())
+}
+
+/// Indices of the services with the given tag.
+fn tagged(entries: &[&registry::Entry], tag: &str) -> Vec<usize> {
+    (0..entries.len())
+        .filter(|&i| entries[i].service().tags().contains(&tag))
+        .collect()
+}
+
+fn first_error(mut errors: Vec<ServiceError>) -> ServiceError {
+    errors.swap_remove(0)
+}
+
+fn warn(message: impl Display) {
+    eprintln!("companion-service: {}", message);
+}
+
+/// Prints errors collected during the automatic startup or shutdown.
+fn report(what: &str, errors: &[ServiceError]) {
+    eprint!("{}", report_string(what, errors));
+}
+
+/// Formats errors collected during the automatic startup or shutdown.
+fn report_string(what: &str, errors: &[ServiceError]) -> String {
+    let mut report = String::new();
+    if errors.is_empty() {
+        return report;
+    }
+
+    report.push_str(&format!(
+        "companion-service: {} service(s) failed during {}:\n",
+        errors.len(),
+        what
+    ));
+    for error in errors {
+        report.push_str(&format!("  - {}\n", error));
     }
+
+    report
 }
 
-/// Stops all services with the given name.
-pub fn stop(name: &str) {
-    for service in SERVICES {
-        if service.name() == name {
-            service.stop();
+/// Starts the selected services concurrently, each after its dependencies,
+/// after cleaning up stale instances left behind by previous runs. Lazy
+/// services are only started if `include_lazy` is set. Nothing is started if
+/// the registered services are invalid.
+fn startup(include_lazy: bool) -> Result<(), Vec<ServiceError>> {
+    validation::validate()?;
+
+    match clean_stale_instances() {
+        Ok(removed) => {
+            for instance in removed {
+                warn(format_args!(
+                    "cleaned up stale instance of service `{}` (pid {}{})",
+                    instance.name,
+                    instance.pid,
+                    if instance.killed { ", killed" } else { "" }
+                ));
+            }
         }
+        Err(error) => warn(format_args!(
+            "failed to clean up stale instances: {}",
+            error
+        )),
+    }
+
+    let entries = registry::entries();
+    let graph = registry::graph(&entries, Phase::Start)?;
+    let order = selection::select(&graph, include_lazy)?;
+    let errors = parallel::start(&entries, &graph, &order);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
     }
 }
 
-/// Restarts all services with the given name.
-pub fn restart(name: &str) {
-    for service in SERVICES {
-        if service.name() == name {
-            service.restart();
+/// Like [`startup`], but stops the services that did start if any failed,
+/// adding the errors from stopping them.
+fn startup_or_rollback(include_lazy: bool) -> Result<(), Vec<ServiceError>> {
+    startup(include_lazy).map_err(|mut errors| {
+        if let Err(stop_errors) = shutdown() {
+            errors.extend(stop_errors);
         }
+
+        errors
+    })
+}
+
+/// Stops all services in reverse dependency order, or in reverse
+/// registration order if the dependencies cannot be resolved.
+fn shutdown() -> Result<(), Vec<ServiceError>> {
+    let entries = registry::entries();
+    let order = registry::graph(&entries, Phase::Stop)
+        .and_then(|graph| graph.order())
+        .unwrap_or_else(|_| (0..entries.len()).collect());
+
+    let errors: Vec<_> = order
+        .into_iter()
+        .rev()
+        .filter_map(|i| entries[i].stop().err())
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
     }
 }
 
+#[cfg(not(feature = "manual"))]
 #[ctor]
 fn init() {
-    for service in SERVICES {
-        service.start();
+    match coordinator::role() {
+        Role::Standalone => {}
+        Role::Coordinator(coordinator) => coordinator.run(),
+        Role::Attached(coordinator) => return coordinator.attach(),
+        Role::Listing => return,
+    }
+
+    if let Err(errors) = startup_or_rollback(false) {
+        report("startup", &errors);
+        STARTUP_FAILED.store(true, Ordering::SeqCst);
+        std::process::exit(STARTUP_FAILURE_EXIT_CODE);
     }
 }
 
+#[cfg(not(feature = "manual"))]
 #[dtor]
 fn deinit() {
-    for service in SERVICES {
-        service.stop();
+    match coordinator::role() {
+        Role::Standalone => {}
+        // The coordinator stops its services before exiting
+        Role::Coordinator(_) | Role::Listing => return,
+        Role::Attached(coordinator) => return coordinator.detach(),
+    }
+    // Everything was already stopped before exiting
+    if STARTUP_FAILED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    if let Err(errors) = shutdown() {
+        report("shutdown", &errors);
     }
 }
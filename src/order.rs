@@ -0,0 +1,206 @@
+//! Dependency resolution between registered services.
+
+use crate::{DependencyError, Phase, Service, ServiceError};
+
+/// Dependency graph over a list of services. Edges point from a service to
+/// the services it depends on. Services are identified by their index in the
+/// list the graph was built from.
+pub(crate) struct Graph<'a> {
+    services: Vec<&'a dyn Service>,
+    dependencies: Vec<Vec<usize>>,
+    /// Phase reported in errors.
+    phase: Phase,
+}
+
+impl<'a> Graph<'a> {
+    /// Builds the graph, failing if any service depends on an unknown name.
+    /// Errors are reported for the phase the graph is built for.
+    pub(crate) fn new(
+        services: Vec<&'a dyn Service>,
+        phase: Phase,
+    ) -> Result<Self, Vec<ServiceError>> {
+        let mut errors = Vec::new();
+        let dependencies = services
+            .iter()
+            .map(|service| {
+                let mut edges = Vec::new();
+                for dependency in service.depends_on() {
+                    let len = edges.len();
+                    edges.extend(
+                        services
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, other)| other.name() == *dependency)
+                            .map(|(i, _)| i),
+                    );
+                    if edges.len() == len {
+                        errors.push(ServiceError::new(
+                            service.name(),
+                            phase,
+                            DependencyError::Missing(dependency.to_string()),
+                        ));
+                    }
+                }
+
+                edges
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(Self {
+                services,
+                dependencies,
+                phase,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns the indices of all services in an order where every service
+    /// comes after its dependencies. Ties are broken by registration order.
+    pub(crate) fn order(&self) -> Result<Vec<usize>, Vec<ServiceError>> {
+        let mut marks = vec![Mark::Unvisited; self.services.len()];
+        let mut order = Vec::with_capacity(self.services.len());
+        let mut errors = Vec::new();
+        for i in 0..self.services.len() {
+            let mut path = Vec::new();
+            if let Err(error) = self.visit(i, &mut marks, &mut path, &mut order)
+            {
+                errors.push(error);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(order)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Like [`Graph::order`], but only includes the services with the given
+    /// name and everything they transitively depend on.
+    pub(crate) fn order_with_dependencies(
+        &self,
+        name: &str,
+    ) -> Result<Vec<usize>, Vec<ServiceError>> {
+        self.filtered_order(&self.with_dependencies(self.named(name)))
+    }
+
+    /// Marks the given services and everything they transitively depend on.
+    pub(crate) fn with_dependencies(
+        &self,
+        mut pending: Vec<usize>,
+    ) -> Vec<bool> {
+        let mut included = vec![false; self.services.len()];
+        while let Some(i) = pending.pop() {
+            if !included[i] {
+                included[i] = true;
+                pending.extend(&self.dependencies[i]);
+            }
+        }
+
+        included
+    }
+
+    /// Like [`Graph::order`], but only includes the services with the given
+    /// name and everything that transitively depends on them.
+    pub(crate) fn order_with_dependents(
+        &self,
+        name: &str,
+    ) -> Result<Vec<usize>, Vec<ServiceError>> {
+        self.filtered_order(&self.with_dependents(self.named(name)))
+    }
+
+    /// Marks the given services and everything that transitively depends on
+    /// them.
+    pub(crate) fn with_dependents(&self, mut pending: Vec<usize>) -> Vec<bool> {
+        let mut included = vec![false; self.services.len()];
+        while let Some(i) = pending.pop() {
+            if !included[i] {
+                included[i] = true;
+                pending.extend(
+                    (0..self.services.len())
+                        .filter(|&j| self.dependencies[j].contains(&i)),
+                );
+            }
+        }
+
+        included
+    }
+
+    /// Indices of the services the given service depends on.
+    pub(crate) fn dependencies(&self, i: usize) -> &[usize] {
+        &self.dependencies[i]
+    }
+
+    /// All services, by index.
+    pub(crate) fn services(
+        &self,
+    ) -> impl Iterator<Item = &'a dyn Service> + '_ {
+        self.services.iter().copied()
+    }
+
+    fn named(&self, name: &str) -> Vec<usize> {
+        (0..self.services.len())
+            .filter(|&i| self.services[i].name() == name)
+            .collect()
+    }
+
+    /// Like [`Graph::order`], but only includes the marked services.
+    pub(crate) fn filtered_order(
+        &self,
+        included: &[bool],
+    ) -> Result<Vec<usize>, Vec<ServiceError>> {
+        Ok(self.order()?.into_iter().filter(|&i| included[i]).collect())
+    }
+
+    fn visit(
+        &self,
+        i: usize,
+        marks: &mut [Mark],
+        path: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), ServiceError> {
+        match marks[i] {
+            Mark::Done => return Ok(()),
+            Mark::Visiting => {
+                let start = path.iter().position(|&j| j == i).unwrap_or(0);
+                let cycle = path[start..]
+                    .iter()
+                    .chain(Some(&i))
+                    .map(|&j| self.services[j].name().to_string())
+                    .collect();
+                // Make sure the cycle is not reported again from another root
+                for &j in path.iter() {
+                    marks[j] = Mark::Done;
+                }
+
+                return Err(ServiceError::new(
+                    self.services[i].name(),
+                    self.phase,
+                    DependencyError::Cycle(cycle),
+                ));
+            }
+            Mark::Unvisited => {}
+        }
+
+        marks[i] = Mark::Visiting;
+        path.push(i);
+        for &dependency in &self.dependencies[i] {
+            self.visit(dependency, marks, path, order)?;
+        }
+        path.pop();
+        marks[i] = Mark::Done;
+        order.push(i);
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Unvisited,
+    Visiting,
+    Done,
+}
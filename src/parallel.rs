@@ -0,0 +1,197 @@
+//! Concurrent startup of independent services.
+
+use crate::{
+    order::Graph, registry::Entry, selection::flag, warn, DependencyError,
+    ServiceError,
+};
+use std::{
+    panic,
+    sync::{Condvar, Mutex, MutexGuard},
+    thread,
+};
+
+/// Forces services to be started one at a time, in dependency order.
+const SEQUENTIAL_VAR: &str = "COMPANION_SERVICES_SEQUENTIAL";
+/// Prints how long each service took to start.
+const TIMINGS_VAR: &str = "COMPANION_SERVICES_TIMINGS";
+
+/// Starts the services at the given indices, which must be in dependency
+/// order. Each service is started on its own thread as soon as its
+/// dependencies are done, unless sequential startup is forced. Services
+/// whose dependencies failed are not started. Returns the errors in the same
+/// order.
+pub(crate) fn start(
+    entries: &[&'static Entry],
+    graph: &Graph<'_>,
+    order: &[usize],
+) -> Vec<ServiceError> {
+    let results = if flag(SEQUENTIAL_VAR) {
+        sequentially(entries, graph, order)
+    } else {
+        concurrently(entries, graph, order)
+    };
+
+    if flag(TIMINGS_VAR) {
+        for &i in order {
+            if let Some(duration) = entries[i].start_duration() {
+                warn(format_args!(
+                    "started service `{}` in {:?}",
+                    entries[i].service().name(),
+                    duration
+                ));
+            }
+        }
+    }
+
+    results.into_iter().filter_map(Result::err).collect()
+}
+
+fn sequentially(
+    entries: &[&'static Entry],
+    graph: &Graph<'_>,
+    order: &[usize],
+) -> Vec<Result<(), ServiceError>> {
+    // Services outside of `order` are not started on purpose, and so do not
+    // hold back their dependents
+    let mut failed = vec![false; entries.len()];
+    order
+        .iter()
+        .map(|&i| {
+            let result =
+                match graph.dependencies(i).iter().find(|&&j| failed[j]) {
+                    Some(&j) => Err(dependency_failed(entries, i, j)),
+                    None => entries[i].start(),
+                };
+            failed[i] = result.is_err();
+            result
+        })
+        .collect()
+}
+
+fn concurrently(
+    entries: &[&'static Entry],
+    graph: &Graph<'_>,
+    order: &[usize],
+) -> Vec<Result<(), ServiceError>> {
+    let mut included = vec![false; entries.len()];
+    for &i in order {
+        included[i] = true;
+    }
+    let progress = Progress {
+        done: Mutex::new(vec![None; entries.len()]),
+        changed: Condvar::new(),
+    };
+
+    thread::scope(|scope| {
+        let threads: Vec<_> = order
+            .iter()
+            .map(|&i| {
+                let (included, progress) = (&included, &progress);
+                let dependencies = graph.dependencies(i);
+                thread::Builder::new()
+                    .name(format!(
+                        "companion-service start {}",
+                        entries[i].service().name()
+                    ))
+                    .spawn_scoped(scope, move || {
+                        let failed = {
+                            let done = progress.wait(|done| {
+                                dependencies
+                                    .iter()
+                                    .all(|&j| !included[j] || done[j].is_some())
+                            });
+                            dependencies.iter().copied().find(|&j| {
+                                included[j] && done[j] == Some(false)
+                            })
+                        };
+                        let mut done = Done {
+                            progress,
+                            index: i,
+                            succeeded: false,
+                        };
+                        let result = match failed {
+                            Some(j) => Err(dependency_failed(entries, i, j)),
+                            None => entries[i].start(),
+                        };
+                        done.succeeded = result.is_ok();
+                        result
+                    })
+                    .map_err(|error| {
+                        // Fails the service, so that its dependents do not
+                        // wait for it forever.
+                        drop(Done {
+                            progress,
+                            index: i,
+                            succeeded: false,
+                        });
+                        ServiceError::start(entries[i].service().name(), error)
+                    })
+            })
+            .collect();
+
+        threads
+            .into_iter()
+            .map(|thread| {
+                thread?
+                    .join()
+                    .unwrap_or_else(|error| panic::resume_unwind(error))
+            })
+            .collect()
+    })
+}
+
+/// Which services are done starting, and whether they succeeded.
+struct Progress {
+    done: Mutex<Vec<Option<bool>>>,
+    changed: Condvar,
+}
+
+impl Progress {
+    fn wait(
+        &self,
+        ready: impl Fn(&[Option<bool>]) -> bool,
+    ) -> MutexGuard<'_, Vec<Option<bool>>> {
+        let mut done =
+            self.done.lock().unwrap_or_else(|error| error.into_inner());
+        while !ready(&done) {
+            done = self
+                .changed
+                .wait(done)
+                .unwrap_or_else(|error| error.into_inner());
+        }
+
+        done
+    }
+}
+
+/// Marks a service as done when dropped, even if starting it panicked, so
+/// that its dependents do not wait forever.
+struct Done<'a> {
+    progress: &'a Progress,
+    index: usize,
+    succeeded: bool,
+}
+
+impl Drop for Done<'_> {
+    fn drop(&mut self) {
+        self.progress
+            .done
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())[self.index] =
+            Some(self.succeeded);
+        self.progress.changed.notify_all();
+    }
+}
+
+/// Error for service `i`, which was not started since its dependency `j`
+/// failed.
+fn dependency_failed(
+    entries: &[&'static Entry],
+    i: usize,
+    j: usize,
+) -> ServiceError {
+    ServiceError::start(
+        entries[i].service().name(),
+        DependencyError::Failed(entries[j].service().name().to_string()),
+    )
+}
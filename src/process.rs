@@ -0,0 +1,691 @@
+//! Services backed by an external process.
+
+use crate::{
+    janitor, spawner, Exit, Location, Readiness, Service, ServiceError,
+    Supervision, Timeouts,
+};
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    panic,
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a stopping process is polled for exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A signal sent to a process to ask it to stop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signal(i32);
+
+impl Signal {
+    /// `SIGTERM`.
+    pub const TERM: Signal = Signal(15);
+    /// `SIGINT`.
+    pub const INT: Signal = Signal(2);
+    /// `SIGQUIT`.
+    pub const QUIT: Signal = Signal(3);
+    /// `SIGHUP`.
+    pub const HUP: Signal = Signal(1);
+    /// `SIGKILL`.
+    pub const KILL: Signal = Signal(9);
+
+    /// Creates a signal from its raw number.
+    pub const fn new(raw: i32) -> Self {
+        Signal(raw)
+    }
+
+    /// Raw number of this signal.
+    pub const fn raw(self) -> i32 {
+        self.0
+    }
+}
+
+/// How a process-backed service ended when it was stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StopOutcome {
+    /// The process exited with code 0, or was terminated by the stop signal.
+    Clean,
+    /// The process exited on its own with a failure status, either before or
+    /// after receiving the stop signal.
+    Exited(ExitStatus),
+    /// The process did not exit within the stop timeout and was killed.
+    Killed,
+}
+
+/// Where the standard input of a process comes from.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Input {
+    /// Reads from `/dev/null`.
+    Null,
+    /// Inherits the standard input of the current process.
+    Inherit,
+    /// Reads from the file at the path.
+    File(PathBuf),
+    /// Reads the given bytes.
+    Bytes(Vec<u8>),
+}
+
+/// A [`Service`] that runs an external program. The program is spawned on
+/// [`Service::start`] and stopped on [`Service::stop`] by sending it the
+/// configured stop signal, followed by `SIGKILL` if it does not exit within
+/// the stop timeout. On Unix the program runs in its own process group and
+/// signals are sent to the whole group, so grandchildren spawned by wrapper
+/// scripts are stopped as well.
+///
+/// On Linux the program is also killed when the current process exits
+/// without running its destructors, e.g. when it is killed with `SIGKILL`.
+/// Only the program itself receives that signal, not the rest of its process
+/// group.
+///
+/// Stopping only fails if the process could not be signalled or waited for.
+/// A process that had to be killed, or that exited with a failure status,
+/// still counts as stopped, so [`stop_all`](crate::stop_all) and the
+/// automatic shutdown do not report it. Use
+/// [`ProcessService::terminate`] or [`ProcessService::last_stop_outcome`] to
+/// find out how it ended.
+///
+/// Since the builder is not `const`, use [`LazyService`](crate::LazyService)
+/// to register a process service in [`SERVICES`](static@crate::SERVICES):
+///
+/// ```rust
+/// use companion_service::{LazyService, ProcessService, Service, SERVICES};
+/// use linkme::distributed_slice;
+///
+/// static SLEEPER: LazyService<ProcessService> = LazyService::new(|| {
+///     ProcessService::builder("sleeper", "sleep").arg("60").build()
+/// });
+///
+/// #[distributed_slice(SERVICES)]
+/// static SLEEPER_SERVICE: &(dyn Service + Sync) = &SLEEPER;
+/// ```
+#[derive(Debug)]
+pub struct ProcessService {
+    name: String,
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    current_dir: Option<PathBuf>,
+    stdin: Input,
+    log_file: Option<PathBuf>,
+    stop_signal: Signal,
+    stop_timeout: Duration,
+    process_group: bool,
+    die_with_parent: bool,
+    data_dirs: Vec<PathBuf>,
+    depends_on: Vec<&'static str>,
+    tags: Vec<&'static str>,
+    readiness: Readiness,
+    shared: bool,
+    lazy: bool,
+    multi_instance: bool,
+    timeouts: Timeouts,
+    supervision: Supervision,
+    location: Location,
+    child: Mutex<Option<Child>>,
+    /// Process started by another process sharing this service.
+    adopted: Mutex<Option<u32>>,
+    /// PID of the running process, or 0. Readable without locking so that
+    /// the process can be killed while another thread is stopping it.
+    running: AtomicU32,
+    last_stop_outcome: Mutex<Option<StopOutcome>>,
+}
+
+impl ProcessService {
+    /// Creates a builder for a service with the given name that runs the
+    /// given program. The caller is reported as the location of the service.
+    #[track_caller]
+    pub fn builder(
+        name: impl Into<String>,
+        program: impl Into<OsString>,
+    ) -> ProcessServiceBuilder {
+        ProcessServiceBuilder {
+            service: ProcessService {
+                name: name.into(),
+                program: program.into(),
+                args: Vec::new(),
+                env: Vec::new(),
+                current_dir: None,
+                stdin: Input::Null,
+                log_file: None,
+                stop_signal: Signal::TERM,
+                stop_timeout: Duration::from_secs(10),
+                process_group: true,
+                die_with_parent: true,
+                data_dirs: Vec::new(),
+                depends_on: Vec::new(),
+                tags: Vec::new(),
+                readiness: Readiness::new(),
+                shared: false,
+                lazy: false,
+                multi_instance: false,
+                timeouts: Timeouts::new(),
+                supervision: Supervision::default(),
+                location: panic::Location::caller().into(),
+                child: Mutex::new(None),
+                adopted: Mutex::new(None),
+                running: AtomicU32::new(0),
+                last_stop_outcome: Mutex::new(None),
+            },
+        }
+    }
+
+    /// PID of the running process, if any.
+    pub fn pid(&self) -> Option<u32> {
+        self.child()
+            .as_ref()
+            .map(Child::id)
+            .or(*lock(&self.adopted))
+    }
+
+    /// How the process ended the last time it was stopped, if it was ever
+    /// stopped. This is the only way to tell whether a process stopped
+    /// through the registry was killed or exited with a failure status.
+    pub fn last_stop_outcome(&self) -> Option<StopOutcome> {
+        *lock(&self.last_stop_outcome)
+    }
+
+    /// Stops the process like [`Service::stop`], returning how it ended.
+    /// Returns `None` if the process was not running.
+    pub fn terminate(&self) -> Result<Option<StopOutcome>, ServiceError> {
+        let child = self.child().take();
+        let adopted = lock(&self.adopted).take();
+        let outcome = match (child, adopted) {
+            (Some(mut child), _) => self.terminate_child(&mut child),
+            (None, Some(pid)) => self.terminate_adopted(pid),
+            (None, None) => return Ok(None),
+        };
+        self.running.store(0, Ordering::SeqCst);
+        let outcome =
+            outcome.map_err(|error| ServiceError::stop(&self.name, error))?;
+        *lock(&self.last_stop_outcome) = Some(outcome);
+
+        Ok(Some(outcome))
+    }
+
+    fn child(&self) -> MutexGuard<'_, Option<Child>> {
+        lock(&self.child)
+    }
+
+    fn command(&self) -> io::Result<Command> {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        #[cfg(unix)]
+        if self.process_group {
+            use std::os::unix::process::CommandExt;
+
+            command.process_group(0);
+        }
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+        command.stdin(match &self.stdin {
+            Input::Null => Stdio::null(),
+            Input::Inherit => Stdio::inherit(),
+            Input::File(path) => File::open(path)?.into(),
+            Input::Bytes(_) => Stdio::piped(),
+        });
+        match &self.log_file {
+            Some(path) => {
+                let log =
+                    OpenOptions::new().create(true).append(true).open(path)?;
+                command.stdout(log.try_clone()?).stderr(log);
+            }
+            None => {
+                command.stdout(Stdio::null()).stderr(Stdio::null());
+            }
+        }
+
+        Ok(command)
+    }
+
+    fn spawn(&self) -> io::Result<Child> {
+        let mut command = self.command()?;
+        // Shared processes must outlive this process if others still use them
+        let mut child = if self.die_with_parent && !self.shared {
+            spawner::spawn(command)?
+        } else {
+            command.spawn()?
+        };
+        if let (Input::Bytes(bytes), Some(mut stdin)) =
+            (&self.stdin, child.stdin.take())
+        {
+            // Written from another thread so a child that does not read its
+            // input cannot block startup
+            let bytes = bytes.clone();
+            thread::spawn(move || stdin.write_all(&bytes));
+        }
+
+        Ok(child)
+    }
+
+    fn terminate_child(&self, child: &mut Child) -> io::Result<StopOutcome> {
+        if peek_exit(child)?.is_some() {
+            return self.reap(child);
+        }
+
+        self.signal(child, self.stop_signal)?;
+        let deadline = Instant::now() + self.stop_timeout;
+        while Instant::now() < deadline {
+            if peek_exit(child)?.is_some() {
+                return self.reap(child);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        self.signal(child, Signal::KILL)?;
+        child.wait()?;
+
+        Ok(StopOutcome::Killed)
+    }
+
+    /// Like [`ProcessService::terminate_child`], for a process that is not a
+    /// child of the current process. Its exit status cannot be known, so it
+    /// is always reported as clean unless it had to be killed.
+    #[cfg(unix)]
+    fn terminate_adopted(&self, pid: u32) -> io::Result<StopOutcome> {
+        let start_time = janitor::start_time(pid);
+        if !janitor::is_alive(pid, start_time) {
+            return Ok(StopOutcome::Clean);
+        }
+
+        self.signal_pid(pid, self.stop_signal)?;
+        let deadline = Instant::now() + self.stop_timeout;
+        while Instant::now() < deadline {
+            if !janitor::is_alive(pid, start_time) {
+                if self.process_group {
+                    let _ = self.signal_pid(pid, Signal::KILL);
+                }
+                return Ok(StopOutcome::Clean);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        self.signal_pid(pid, Signal::KILL)?;
+
+        Ok(StopOutcome::Killed)
+    }
+
+    #[cfg(not(unix))]
+    fn terminate_adopted(&self, _: u32) -> io::Result<StopOutcome> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cannot stop a process started by another process",
+        ))
+    }
+
+    fn outcome(&self, status: ExitStatus) -> StopOutcome {
+        if status.success()
+            || exit_signal(&status) == Some(self.stop_signal.raw())
+        {
+            StopOutcome::Clean
+        } else {
+            StopOutcome::Exited(status)
+        }
+    }
+
+    /// Kills whatever is left of the process group after its leader exited,
+    /// then reaps the leader. Until then, the leader keeps its PID and
+    /// process group ID from being reused.
+    fn reap(&self, child: &mut Child) -> io::Result<StopOutcome> {
+        if self.process_group {
+            // Fails if the group is already empty, which is the common case
+            let _ = self.signal(child, Signal::KILL);
+        }
+
+        Ok(self.outcome(child.wait()?))
+    }
+
+    #[cfg(unix)]
+    fn signal(&self, child: &mut Child, signal: Signal) -> io::Result<()> {
+        // The PID cannot have been reused since the child was not reaped
+        // yet, and neither can its process group ID
+        self.signal_pid(child.id(), signal)
+    }
+
+    #[cfg(unix)]
+    fn signal_pid(&self, pid: u32, signal: Signal) -> io::Result<()> {
+        let pid = pid as libc::pid_t;
+        let target = if self.process_group { -pid } else { pid };
+        if unsafe { libc::kill(target, signal.raw()) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&self, child: &mut Child, _: Signal) -> io::Result<()> {
+        child.kill()
+    }
+}
+
+impl Service for ProcessService {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        &self.depends_on
+    }
+
+    fn tags(&self) -> &[&str] {
+        &self.tags
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+
+    fn pid(&self) -> Option<u32> {
+        ProcessService::pid(self)
+    }
+
+    fn data_dirs(&self) -> Vec<PathBuf> {
+        self.data_dirs.clone()
+    }
+
+    fn shared_between_processes(&self) -> bool {
+        self.shared
+    }
+
+    fn lazy(&self) -> bool {
+        self.lazy
+    }
+
+    fn location(&self) -> Option<Location> {
+        Some(self.location)
+    }
+
+    fn multi_instance(&self) -> bool {
+        self.multi_instance
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    fn kill(&self) -> bool {
+        match self.running.load(Ordering::SeqCst) {
+            0 => false,
+            pid => {
+                janitor::kill(pid);
+                cfg!(unix)
+            }
+        }
+    }
+
+    fn supervision(&self) -> Supervision {
+        self.supervision
+    }
+
+    fn exited(&self) -> Option<Exit> {
+        // Reaped when stopped, see `ProcessService::reap`
+        peek_exit(self.child().as_mut()?).ok().flatten()
+    }
+
+    fn attach(&self, pid: Option<u32>) -> Result<(), ServiceError> {
+        *lock(&self.adopted) = pid;
+        self.running.store(pid.unwrap_or(0), Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn detach(&self) -> Result<(), ServiceError> {
+        // Dropping the handle leaves the process running
+        self.child().take();
+        lock(&self.adopted).take();
+        self.running.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        let mut child = self.child();
+        if let Some(running) = child.as_mut() {
+            match running.try_wait() {
+                Ok(None) => return Ok(()),
+                Ok(Some(_)) => {}
+                Err(error) => {
+                    return Err(ServiceError::start(&self.name, error))
+                }
+            }
+        }
+        if let Some(pid) = *lock(&self.adopted) {
+            if janitor::is_alive(pid, None) {
+                return Ok(());
+            }
+        }
+
+        let spawned = self
+            .spawn()
+            .map_err(|error| ServiceError::start(&self.name, error))?;
+        self.running.store(spawned.id(), Ordering::SeqCst);
+        *child = Some(spawned);
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        self.terminate().map(drop)
+    }
+}
+
+/// How the child exited, if it did. On Unix, the child is not reaped so that
+/// its process group can still be killed safely.
+#[cfg(unix)]
+fn peek_exit(child: &mut Child) -> io::Result<Option<Exit>> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            child.id() as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Left zeroed if the child is still running
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(None);
+    }
+    let success =
+        info.si_code == libc::CLD_EXITED && unsafe { info.si_status() } == 0;
+    Ok(Some(if success {
+        Exit::Success
+    } else {
+        Exit::Failure
+    }))
+}
+
+#[cfg(not(unix))]
+fn peek_exit(child: &mut Child) -> io::Result<Option<Exit>> {
+    Ok(child.try_wait()?.map(|status| {
+        if status.success() {
+            Exit::Success
+        } else {
+            Exit::Failure
+        }
+    }))
+}
+
+/// Builder for [`ProcessService`].
+#[derive(Debug)]
+pub struct ProcessServiceBuilder {
+    service: ProcessService,
+}
+
+impl ProcessServiceBuilder {
+    /// Adds an argument.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.service.args.push(arg.into());
+        self
+    }
+
+    /// Adds multiple arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.service.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets an environment variable. The rest of the environment is
+    /// inherited from the current process.
+    pub fn env(
+        mut self,
+        key: impl Into<OsString>,
+        value: impl Into<OsString>,
+    ) -> Self {
+        self.service.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the working directory.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.service.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the standard input. Defaults to [`Input::Null`].
+    pub fn stdin(mut self, stdin: Input) -> Self {
+        self.service.stdin = stdin;
+        self
+    }
+
+    /// Appends both standard output and standard error to the file at the
+    /// path. Both are discarded by default.
+    pub fn log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.service.log_file = Some(path.into());
+        self
+    }
+
+    /// Sets the signal sent to ask the process to stop. Defaults to
+    /// [`Signal::TERM`].
+    pub fn stop_signal(mut self, signal: Signal) -> Self {
+        self.service.stop_signal = signal;
+        self
+    }
+
+    /// Sets how long to wait for the process to exit after the stop signal
+    /// before sending `SIGKILL`. Defaults to 10 seconds.
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.service.stop_timeout = timeout;
+        self
+    }
+
+    /// Sets whether the process runs in its own process group, with signals
+    /// sent to the whole group. Only has an effect on Unix. Defaults to
+    /// `true`.
+    pub fn process_group(mut self, enabled: bool) -> Self {
+        self.service.process_group = enabled;
+        self
+    }
+
+    /// Sets whether the process is killed as soon as the current process
+    /// exits, even if it does so without running destructors. Only has an
+    /// effect on Linux. Defaults to `true`.
+    pub fn die_with_parent(mut self, enabled: bool) -> Self {
+        self.service.die_with_parent = enabled;
+        self
+    }
+
+    /// Sets whether a single instance of the process is shared by all
+    /// processes using the same state directory. Shared processes are not
+    /// killed when the current process exits, since others may still use
+    /// them. See [`Service::shared_between_processes`]. Defaults to `false`.
+    pub fn shared(mut self, enabled: bool) -> Self {
+        self.service.shared = enabled;
+        self
+    }
+
+    /// Sets whether the process is only started when first needed. See
+    /// [`Service::lazy`]. Defaults to `false`.
+    pub fn lazy(mut self, enabled: bool) -> Self {
+        self.service.lazy = enabled;
+        self
+    }
+
+    /// Sets whether the service may share its name with other services. See
+    /// [`Service::multi_instance`]. Defaults to `false`.
+    pub fn multi_instance(mut self, enabled: bool) -> Self {
+        self.service.multi_instance = enabled;
+        self
+    }
+
+    /// Sets the timeouts for starting, stopping and restarting the service.
+    /// See [`Service::timeouts`]. Defaults to the global ones.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.service.timeouts = timeouts;
+        self
+    }
+
+    /// Sets whether and how the process is restarted after exiting on its
+    /// own. See [`Service::supervision`]. Defaults to never restarting it.
+    pub fn supervision(mut self, supervision: Supervision) -> Self {
+        self.service.supervision = supervision;
+        self
+    }
+
+    /// Adds a data directory. See [`Service::data_dirs`].
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.service.data_dirs.push(dir.into());
+        self
+    }
+
+    /// Adds a dependency. See [`Service::depends_on`].
+    pub fn depends_on(mut self, name: &'static str) -> Self {
+        self.service.depends_on.push(name);
+        self
+    }
+
+    /// Adds a tag. See [`Service::tags`].
+    pub fn tag(mut self, tag: &'static str) -> Self {
+        self.service.tags.push(tag);
+        self
+    }
+
+    /// Sets the readiness probes. See [`Service::readiness`].
+    pub fn readiness(mut self, readiness: Readiness) -> Self {
+        self.service.readiness = readiness;
+        self
+    }
+
+    /// Builds the service.
+    pub fn build(self) -> ProcessService {
+        self.service
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The data is still valid if another thread panicked while holding it
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_: &ExitStatus) -> Option<i32> {
+    None
+}
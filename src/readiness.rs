@@ -0,0 +1,376 @@
+//! Readiness probes, used to wait until a started service actually accepts
+//! work.
+
+use regex::Regex;
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Timeout used for each individual network operation of a probe.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often a command probe is checked for having exited.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A single readiness check.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Probe {
+    /// Ready when a TCP connection to the address can be established.
+    Tcp(String),
+    /// Ready when a `GET` request to the URL returns the given status code.
+    /// Only plain `http://` URLs are supported.
+    Http { url: String, status: u16 },
+    /// Ready when a Unix socket exists at the path.
+    UnixSocket(PathBuf),
+    /// Ready when a file exists at the path.
+    File(PathBuf),
+    /// Ready when the command exits with the given code.
+    Command {
+        program: String,
+        args: Vec<String>,
+        code: i32,
+    },
+    /// Ready when any line of the file at the path matches the pattern.
+    LogLine { path: PathBuf, pattern: Regex },
+}
+
+impl Probe {
+    /// Probe for a TCP address, e.g. `"127.0.0.1:5432"`.
+    pub fn tcp(address: impl Into<String>) -> Self {
+        Probe::Tcp(address.into())
+    }
+
+    /// Probe for a `GET` request returning `200 OK`.
+    pub fn http(url: impl Into<String>) -> Self {
+        Self::http_status(url, 200)
+    }
+
+    /// Probe for a `GET` request returning the given status code.
+    pub fn http_status(url: impl Into<String>, status: u16) -> Self {
+        Probe::Http {
+            url: url.into(),
+            status,
+        }
+    }
+
+    /// Probe for the existence of a Unix socket.
+    pub fn unix_socket(path: impl Into<PathBuf>) -> Self {
+        Probe::UnixSocket(path.into())
+    }
+
+    /// Probe for the existence of a file.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Probe::File(path.into())
+    }
+
+    /// Probe for a command exiting successfully.
+    pub fn command<I, S>(program: impl Into<String>, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::command_status(program, args, 0)
+    }
+
+    /// Probe for a command exiting with the given code.
+    pub fn command_status<I, S>(
+        program: impl Into<String>,
+        args: I,
+        code: i32,
+    ) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Probe::Command {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            code,
+        }
+    }
+
+    /// Probe for a line matching the pattern in a log file.
+    pub fn log_line(path: impl Into<PathBuf>, pattern: Regex) -> Self {
+        Probe::LogLine {
+            path: path.into(),
+            pattern,
+        }
+    }
+
+    /// Runs the probe once.
+    pub fn check(&self) -> io::Result<()> {
+        self.check_until(None)
+    }
+
+    /// Runs the probe once, giving up on commands still running at the
+    /// deadline.
+    fn check_until(&self, deadline: Option<Instant>) -> io::Result<()> {
+        match self {
+            Probe::Tcp(address) => connect(address).map(drop),
+            Probe::Http { url, status } => check_http(url, *status),
+            Probe::UnixSocket(path) => {
+                let metadata = fs::metadata(path)?;
+                if is_socket(&metadata) {
+                    Ok(())
+                } else {
+                    Err(not_ready("not a socket"))
+                }
+            }
+            Probe::File(path) => fs::metadata(path).map(drop),
+            Probe::Command {
+                program,
+                args,
+                code,
+            } => {
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+                let status = loop {
+                    if let Some(status) = child.try_wait()? {
+                        break status;
+                    }
+                    if deadline
+                        .is_some_and(|deadline| Instant::now() >= deadline)
+                    {
+                        child.kill()?;
+                        child.wait()?;
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "still running",
+                        ));
+                    }
+
+                    thread::sleep(COMMAND_POLL_INTERVAL);
+                };
+                if status.code() == Some(*code) {
+                    Ok(())
+                } else {
+                    Err(not_ready(format!("exited with {}", status)))
+                }
+            }
+            Probe::LogLine { path, pattern } => {
+                let log = fs::read_to_string(path)?;
+                if log.lines().any(|line| pattern.is_match(line)) {
+                    Ok(())
+                } else {
+                    Err(not_ready("no matching line"))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probe::Tcp(address) => write!(f, "tcp {}", address),
+            Probe::Http { url, status } => {
+                write!(f, "http {} ({})", url, status)
+            }
+            Probe::UnixSocket(path) => {
+                write!(f, "unix socket {}", path.display())
+            }
+            Probe::File(path) => write!(f, "file {}", path.display()),
+            Probe::Command { program, args, .. } => {
+                write!(f, "command `{}", program)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                f.write_str("`")
+            }
+            Probe::LogLine { path, pattern } => {
+                write!(f, "log line /{}/ in {}", pattern, path.display())
+            }
+        }
+    }
+}
+
+/// Set of probes that must all pass for a service to be considered ready,
+/// along with how often and for how long to poll them.
+#[derive(Clone, Debug)]
+pub struct Readiness {
+    probes: Vec<Probe>,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl Readiness {
+    /// Creates a readiness check without probes, polling every 100ms for up
+    /// to 30s.
+    pub fn new() -> Self {
+        Self {
+            probes: Vec::new(),
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Adds a probe.
+    pub fn probe(mut self, probe: Probe) -> Self {
+        self.probes.push(probe);
+        self
+    }
+
+    /// Sets the time between polls.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how long to wait for all probes to pass.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Probes added so far.
+    pub fn probes(&self) -> &[Probe] {
+        &self.probes
+    }
+
+    /// Polls the probes until all of them pass or the timeout expires.
+    /// Command probes still running when it expires are killed.
+    pub fn wait(&self) -> Result<(), ReadinessError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut pending = self.probes.iter();
+        let mut current = pending.next();
+        while let Some(probe) = current {
+            match probe.check_until(Some(deadline)) {
+                Ok(()) => current = pending.next(),
+                Err(error) => {
+                    if Instant::now() >= deadline {
+                        return Err(ReadinessError {
+                            probe: probe.to_string(),
+                            timeout: self.timeout,
+                            last_error: error,
+                        });
+                    }
+
+                    thread::sleep(self.interval);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned when a service does not become ready in time.
+#[derive(Debug)]
+pub struct ReadinessError {
+    probe: String,
+    timeout: Duration,
+    last_error: io::Error,
+}
+
+impl ReadinessError {
+    /// Description of the probe that did not pass.
+    pub fn probe(&self) -> &str {
+        &self.probe
+    }
+}
+
+impl fmt::Display for ReadinessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "not ready after {:?}: {}: {}",
+            self.timeout, self.probe, self.last_error
+        )
+    }
+}
+
+impl Error for ReadinessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.last_error)
+    }
+}
+
+fn not_ready(message: impl Into<String>) -> io::Error {
+    io::Error::other(message.into())
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = not_ready("address did not resolve");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, ATTEMPT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = error,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn check_http(url: &str, expected: u16) -> io::Result<()> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| not_ready("only http:// URLs are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = connect(&address)?;
+    stream.set_read_timeout(Some(ATTEMPT_TIMEOUT))?;
+    stream.set_write_timeout(Some(ATTEMPT_TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    )?;
+
+    // Only the status line is needed
+    let mut buffer = [0; 32];
+    let mut len = 0;
+    while len < buffer.len() {
+        match stream.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let response = String::from_utf8_lossy(&buffer[..len]);
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| not_ready("malformed HTTP response"))?;
+    if status == expected {
+        Ok(())
+    } else {
+        Err(not_ready(format!("HTTP status {}", status)))
+    }
+}
+
+#[cfg(unix)]
+fn is_socket(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    metadata.file_type().is_socket()
+}
+
+#[cfg(not(unix))]
+fn is_socket(_: &fs::Metadata) -> bool {
+    false
+}
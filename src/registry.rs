@@ -0,0 +1,528 @@
+//! Runtime bookkeeping for registered services.
+
+use crate::{
+    coordinator::{self, Request},
+    janitor,
+    order::Graph,
+    scoped::Scope,
+    shared,
+    supervisor::{self, Supervised},
+    validation, warn, watchdog, Exit, Location, PanicError, Phase, Service,
+    ServiceError, ServiceState, Supervision, SERVICES,
+};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        TryLockError,
+    },
+    time::{Duration, Instant},
+};
+
+/// A registered service along with its runtime state.
+pub(crate) struct Entry {
+    service: &'static (dyn Service + Sync),
+    /// Tells apart instances of [`multi_instance`](Service::multi_instance)
+    /// services in the janitor's records.
+    id: u64,
+    /// Where the service is registered, if known.
+    location: Option<Location>,
+    state: Mutex<ServiceState>,
+    /// Index of the service in the coordinator that owns it, if any. See
+    /// [`Entry::attach`].
+    coordinated: Mutex<Option<usize>>,
+    /// How long the last successful start took.
+    start_duration: Mutex<Option<Duration>>,
+    /// Serializes lifecycle operations.
+    lifecycle: Mutex<()>,
+    /// Coordinates tests using the service. See [`crate::exclusive`].
+    access: RwLock<()>,
+    /// Reference count of the guards returned by [`crate::scoped`].
+    scope: Mutex<Scope>,
+    /// Crashes and pending restarts. See [`Service::supervision`].
+    supervised: Mutex<Supervised>,
+}
+
+impl Entry {
+    fn new(
+        service: &'static (dyn Service + Sync),
+        location: Option<Location>,
+    ) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            service,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            location,
+            state: Mutex::new(ServiceState::Stopped),
+            coordinated: Mutex::new(None),
+            start_duration: Mutex::new(None),
+            lifecycle: Mutex::new(()),
+            access: RwLock::new(()),
+            scope: Mutex::new(Scope::default()),
+            supervised: Mutex::new(Supervised::default()),
+        }
+    }
+
+    pub(crate) fn service(&self) -> &'static (dyn Service + Sync) {
+        self.service
+    }
+
+    pub(crate) fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    pub(crate) fn state(&self) -> ServiceState {
+        *self.lock_state()
+    }
+
+    pub(crate) fn start_duration(&self) -> Option<Duration> {
+        *lock(&self.start_duration)
+    }
+
+    /// How many times the service exited on its own.
+    pub(crate) fn crashes(&self) -> u32 {
+        lock(&self.supervised).crashes
+    }
+
+    pub(crate) fn access(&self) -> &RwLock<()> {
+        &self.access
+    }
+
+    pub(crate) fn scope(&self) -> MutexGuard<'_, Scope> {
+        lock(&self.scope)
+    }
+
+    /// Starts the service and waits until it is ready. Does nothing if the
+    /// service is already running.
+    pub(crate) fn start(&self) -> Result<(), ServiceError> {
+        let _lifecycle = self.lock_lifecycle();
+        self.forget_restarts();
+        self.start_locked()
+    }
+
+    /// Stops the service. Does nothing if the service is already stopped.
+    /// Services that failed are stopped too, since they may have been left
+    /// partially running.
+    pub(crate) fn stop(&self) -> Result<(), ServiceError> {
+        let _lifecycle = self.lock_lifecycle();
+        self.forget_restarts();
+        self.stop_locked()
+    }
+
+    /// Restarts the service and waits until it is ready. Stopped services
+    /// are simply started.
+    pub(crate) fn restart(&self) -> Result<(), ServiceError> {
+        let _lifecycle = self.lock_lifecycle();
+        self.forget_restarts();
+        if let Some(index) = self.coordinated() {
+            return self.forward(Request::Restart, index);
+        }
+        if self.state() == ServiceState::Stopped {
+            return self.start_locked();
+        }
+
+        self.set_state(ServiceState::Stopping);
+        let _watch = watchdog::watch(self.service, Phase::Restart);
+        let result = self.isolate(Phase::Restart, || {
+            if self.service.shared_between_processes() {
+                shared::restart(self.service)
+            } else {
+                self.service.restart()
+            }
+            .and_then(|()| {
+                self.set_state(ServiceState::Starting);
+                self.record();
+                self.wait_ready()
+            })
+        });
+        self.settle(result, ServiceState::Running)
+    }
+
+    /// Resets the service and waits until it is ready. Does nothing if the
+    /// service is not running.
+    pub(crate) fn reset(&self) -> Result<(), ServiceError> {
+        let _lifecycle = self.lock_lifecycle();
+        if self.state() != ServiceState::Running {
+            return Ok(());
+        }
+        if let Some(index) = self.coordinated() {
+            return self.forward(Request::Reset, index);
+        }
+
+        let _watch = watchdog::watch(self.service, Phase::Restart);
+        let result = self.isolate(Phase::Restart, || {
+            self.service.reset().and_then(|()| {
+                self.record();
+                self.wait_ready()
+            })
+        });
+        self.settle(result, ServiceState::Running)
+    }
+
+    /// Marks the service as running on behalf of the coordinator, where it
+    /// has the given index. From then on, stopping the service only detaches
+    /// from it and starting it attaches to it again, while restarts and
+    /// resets are carried out by the coordinator. See [`Service::attach`].
+    pub(crate) fn attach(
+        &self,
+        index: usize,
+        pid: Option<u32>,
+    ) -> Result<(), ServiceError> {
+        let _lifecycle = self.lock_lifecycle();
+        *lock(&self.coordinated) = Some(index);
+        let result = self.isolate(Phase::Start, || self.service.attach(pid));
+        self.settle(result, ServiceState::Running)
+    }
+
+    /// Restarts the service according to its [`Service::supervision`] if it
+    /// exited on its own. Called periodically by the supervisor thread.
+    pub(crate) fn supervise(&self) {
+        // Other processes may still be using shared services
+        if self.service.shared_between_processes() {
+            return;
+        }
+
+        // Services busy with another lifecycle operation are checked on the
+        // next round instead of holding up the others
+        let _lifecycle = match self.lifecycle.try_lock() {
+            Ok(lifecycle) => lifecycle,
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        match self.state() {
+            ServiceState::Running => {
+                let exited = panic::catch_unwind(AssertUnwindSafe(|| {
+                    self.service.exited()
+                }));
+                match exited {
+                    Ok(Some(exit)) => self.crashed(exit),
+                    Ok(None) => {}
+                    Err(payload) => {
+                        warn(format_args!(
+                            "failed to check whether service `{}` exited: {}",
+                            self.service.name(),
+                            PanicError::new(payload)
+                        ));
+                        self.set_state(ServiceState::Failed);
+                    }
+                }
+            }
+            ServiceState::Failed => {
+                let due = lock(&self.supervised).due;
+                if due.is_some_and(|due| due <= Instant::now()) {
+                    self.revive();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn stop_locked(&self) -> Result<(), ServiceError> {
+        if self.state() == ServiceState::Stopped {
+            return Ok(());
+        }
+
+        self.set_state(ServiceState::Stopping);
+        let _watch = watchdog::watch(self.service, Phase::Stop);
+        let result = self.isolate(Phase::Stop, || {
+            // The coordinator keeps the service running
+            if self.coordinated().is_some() {
+                self.service.detach()
+            } else if self.service.shared_between_processes() {
+                shared::stop(self.service)
+            } else {
+                self.service.stop().map(|()| self.forget())
+            }
+        });
+        self.settle(result, ServiceState::Stopped)
+    }
+
+    fn start_locked(&self) -> Result<(), ServiceError> {
+        if self.state() == ServiceState::Running {
+            return Ok(());
+        }
+
+        // Attached to again rather than started twice
+        if let Some(index) = self.coordinated() {
+            let result = coordinator::running_pid(index)
+                .map_err(|error| {
+                    ServiceError::start(self.service.name(), error)
+                })
+                .and_then(|pid| {
+                    self.isolate(Phase::Start, || self.service.attach(pid))
+                });
+            return self.settle(result, ServiceState::Running);
+        }
+
+        self.set_state(ServiceState::Starting);
+        let started = Instant::now();
+        let _watch = watchdog::watch(self.service, Phase::Start);
+        let result = self.isolate(Phase::Start, || {
+            if self.service.shared_between_processes() {
+                shared::start(self.service)
+            } else {
+                self.service.start()
+            }
+            .and_then(|()| {
+                self.record();
+                self.wait_ready()
+            })
+        });
+        if result.is_ok() {
+            *lock(&self.start_duration) = Some(started.elapsed());
+            if !self.service.shared_between_processes() {
+                supervisor::spawn();
+            }
+        }
+        self.settle(result, ServiceState::Running)
+    }
+
+    /// Cleans up after the service exited on its own, and schedules a
+    /// restart if its policy asks for one.
+    fn crashed(&self, exit: Exit) {
+        lock(&self.supervised).crashes += 1;
+        warn(format_args!(
+            "service `{}` exited unexpectedly{}",
+            self.service.name(),
+            match exit {
+                Exit::Success => "",
+                _ => " with a failure",
+            }
+        ));
+        // Whatever the service left behind, such as child processes, is
+        // cleaned up before restarting it
+        if let Err(error) = self.stop_locked() {
+            warn(error);
+        }
+
+        let supervision = self.service.supervision();
+        if supervision.restarts_after(exit) {
+            self.schedule(&supervision);
+        } else if exit != Exit::Success {
+            self.set_state(ServiceState::Failed);
+        }
+    }
+
+    /// Restarts the service once its backoff expired.
+    fn revive(&self) {
+        lock(&self.supervised).due = None;
+        warn(format_args!("restarting service `{}`", self.service.name()));
+        if let Err(error) = self.start_locked() {
+            warn(error);
+            self.schedule(&self.service.supervision());
+        }
+    }
+
+    /// Schedules the next restart, or gives up if the attempts are
+    /// exhausted. Either way the service is failed until restarted.
+    fn schedule(&self, supervision: &Supervision) {
+        let mut supervised = lock(&self.supervised);
+        supervised.attempts += 1;
+        supervised.due = supervision
+            .delay(supervised.attempts)
+            .map(|delay| Instant::now() + delay);
+        if supervised.due.is_none() {
+            warn(format_args!(
+                "giving up on service `{}` after {} restart(s)",
+                self.service.name(),
+                supervised.attempts - 1
+            ));
+        }
+        drop(supervised);
+
+        self.set_state(ServiceState::Failed);
+    }
+
+    /// Has the coordinator carry out the request on its instance of the
+    /// service, then attaches to the instance it ends up with.
+    fn forward(
+        &self,
+        request: Request,
+        index: usize,
+    ) -> Result<(), ServiceError> {
+        let result = coordinator::forward(request, index)
+            .map_err(|error| {
+                ServiceError::new(self.service.name(), Phase::Restart, error)
+            })
+            .and_then(|pid| {
+                self.isolate(Phase::Restart, || {
+                    self.service
+                        .detach()
+                        .and_then(|()| self.service.attach(pid))
+                })
+            });
+        self.settle(result, ServiceState::Running)
+    }
+
+    fn coordinated(&self) -> Option<usize> {
+        *lock(&self.coordinated)
+    }
+
+    /// Cancels pending restarts and starts counting attempts over, since
+    /// the service is being handled explicitly.
+    fn forget_restarts(&self) {
+        let mut supervised = lock(&self.supervised);
+        supervised.attempts = 0;
+        supervised.due = None;
+    }
+
+    /// Calls into the service, turning panics into errors so that a single
+    /// service cannot prevent the others from being handled.
+    fn isolate(
+        &self,
+        phase: Phase,
+        hook: impl FnOnce() -> Result<(), ServiceError>,
+    ) -> Result<(), ServiceError> {
+        panic::catch_unwind(AssertUnwindSafe(hook)).unwrap_or_else(|payload| {
+            Err(ServiceError::new(
+                self.service.name(),
+                phase,
+                PanicError::new(payload),
+            ))
+        })
+    }
+
+    fn wait_ready(&self) -> Result<(), ServiceError> {
+        self.service
+            .readiness()
+            .wait()
+            .map_err(|error| ServiceError::start(self.service.name(), error))
+    }
+
+    /// Records the service for the janitor. Shared services are not recorded,
+    /// since they outlive the process that started them.
+    fn record(&self) {
+        if self.service.shared_between_processes() {
+            return;
+        }
+
+        if let Err(error) = janitor::record(self.service, self.id) {
+            warn(format_args!(
+                "failed to record the state of service `{}`: {}",
+                self.service.name(),
+                error
+            ));
+        }
+    }
+
+    fn forget(&self) {
+        if let Err(error) = janitor::forget(self.service, self.id) {
+            warn(format_args!(
+                "failed to remove the state of service `{}`: {}",
+                self.service.name(),
+                error
+            ));
+        }
+    }
+
+    fn settle(
+        &self,
+        result: Result<(), ServiceError>,
+        success: ServiceState,
+    ) -> Result<(), ServiceError> {
+        self.set_state(match result {
+            Ok(()) => success,
+            Err(_) => ServiceState::Failed,
+        });
+
+        result
+    }
+
+    fn set_state(&self, state: ServiceState) {
+        *self.lock_state() = state;
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, ServiceState> {
+        lock(&self.state)
+    }
+
+    fn lock_lifecycle(&self) -> MutexGuard<'_, ()> {
+        lock(&self.lifecycle)
+    }
+}
+
+/// All registered services, in registration order. Services registered at
+/// runtime come after the ones in [`SERVICES`].
+pub(crate) fn entries() -> Vec<&'static Entry> {
+    read(registry()).clone()
+}
+
+/// Registered services with the given name.
+pub(crate) fn named(name: &str) -> impl Iterator<Item = &'static Entry> + '_ {
+    entries()
+        .into_iter()
+        .filter(move |entry| entry.service.name() == name)
+}
+
+/// Adds a service at runtime, failing if it cannot coexist with the
+/// registered ones.
+pub(crate) fn register(
+    service: &'static (dyn Service + Sync),
+    location: Option<Location>,
+) -> Result<(), ServiceError> {
+    let entry = Entry::new(service, location);
+    let mut entries = write(registry());
+    validation::check(&entries, &entry)?;
+    // Leaked since guards and other services may still refer to it after
+    // it is unregistered
+    entries.push(Box::leak(Box::new(entry)));
+
+    Ok(())
+}
+
+/// Removes all services with the given name. Returns whether there were any.
+pub(crate) fn unregister(name: &str) -> bool {
+    let mut entries = write(registry());
+    let len = entries.len();
+    entries.retain(|entry| entry.service.name() != name);
+
+    entries.len() != len
+}
+
+/// Dependency graph over the given services, for an operation in the given
+/// phase. Indices in the graph are indices into `entries`.
+pub(crate) fn graph(
+    entries: &[&'static Entry],
+    phase: Phase,
+) -> Result<Graph<'static>, Vec<ServiceError>> {
+    Graph::new(
+        entries
+            .iter()
+            .map(|entry| entry.service as &dyn Service)
+            .collect(),
+        phase,
+    )
+}
+
+fn registry() -> &'static RwLock<Vec<&'static Entry>> {
+    static REGISTRY: OnceLock<RwLock<Vec<&'static Entry>>> = OnceLock::new();
+
+    REGISTRY.get_or_init(|| {
+        RwLock::new(
+            SERVICES
+                .iter()
+                .map(|&service| {
+                    &*Box::leak(Box::new(Entry::new(
+                        service,
+                        service.location(),
+                    )))
+                })
+                .collect(),
+        )
+    })
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|error| error.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|error| error.into_inner())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The state is still valid if another thread panicked while holding it
+    mutex.lock().unwrap_or_else(|error| error.into_inner())
+}
@@ -0,0 +1,71 @@
+//! Services scoped to the lifetime of a guard.
+
+use crate::{
+    registry::{self, Entry},
+    start_with_dependencies, warn, ServiceError, ServiceState, UnknownService,
+};
+
+/// Reference count of the [`Scoped`] guards for a service.
+#[derive(Default)]
+pub(crate) struct Scope {
+    guards: usize,
+    /// Whether the first guard started the service, and so the last one
+    /// should stop it.
+    started: bool,
+}
+
+/// Guard returned by [`scoped`]. The service is stopped when the last guard
+/// for it is dropped, unless it was already running when the first one was
+/// acquired.
+#[must_use = "the service is stopped as soon as the guard is dropped"]
+pub struct Scoped {
+    entries: Vec<&'static Entry>,
+}
+
+impl Drop for Scoped {
+    fn drop(&mut self) {
+        for entry in &self.entries {
+            let mut scope = entry.scope();
+            scope.guards -= 1;
+            if scope.guards > 0 || !scope.started {
+                continue;
+            }
+
+            scope.started = false;
+            if let Err(error) = entry.stop() {
+                warn(error);
+            }
+        }
+    }
+}
+
+/// Starts all services with the given name, along with everything they
+/// transitively depend on, and returns a guard that stops them again when
+/// dropped. Returns once they are ready.
+///
+/// Guards are reference counted, so nested or concurrent guards for the same
+/// service only start and stop it once. Services that were already running
+/// when the first guard was acquired are left running, and so are
+/// dependencies. Fails with [`UnknownService`] if there is no such service.
+pub fn scoped(name: &str) -> Result<Scoped, ServiceError> {
+    let entries: Vec<_> = registry::named(name).collect();
+    if entries.is_empty() {
+        return Err(ServiceError::start(name, UnknownService));
+    }
+
+    // Held while starting so concurrent guards wait for the service
+    let mut scopes: Vec<_> =
+        entries.iter().map(|entry| entry.scope()).collect();
+    for (entry, scope) in entries.iter().zip(&mut scopes) {
+        if scope.guards == 0 {
+            scope.started = entry.state() != ServiceState::Running;
+        }
+    }
+
+    start_with_dependencies(name)?;
+    for scope in &mut scopes {
+        scope.guards += 1;
+    }
+
+    Ok(Scoped { entries })
+}
@@ -0,0 +1,174 @@
+//! Selection of the services started automatically, through environment
+//! variables.
+
+use crate::{order::Graph, warn, Service, ServiceError};
+use std::{env, fmt};
+
+/// Comma-separated patterns of the services to start. All services are
+/// started if unset. Patterns starting with `tag:` match tags instead of
+/// names.
+const ONLY_VAR: &str = "COMPANION_SERVICES";
+/// Comma-separated patterns of the services not to start.
+const SKIP_VAR: &str = "COMPANION_SERVICES_SKIP";
+/// Disables the automatic startup of all services.
+const DISABLE_VAR: &str = "COMPANION_SERVICES_DISABLE";
+
+/// Why a service is not started automatically.
+#[derive(Clone, Copy)]
+enum Reason {
+    NotSelected,
+    Skipped,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::NotSelected => write!(f, "not selected by {}", ONLY_VAR),
+            Reason::Skipped => write!(f, "skipped by {}", SKIP_VAR),
+        }
+    }
+}
+
+/// The services selected through the environment.
+struct Selection {
+    only: Option<Vec<String>>,
+    skip: Vec<String>,
+}
+
+impl Selection {
+    fn from_env() -> Self {
+        Self {
+            only: patterns(ONLY_VAR),
+            skip: patterns(SKIP_VAR).unwrap_or_default(),
+        }
+    }
+
+    fn is_selected(&self, service: &dyn Service) -> bool {
+        match &self.only {
+            Some(only) => matches_any(only, service),
+            None => true,
+        }
+    }
+
+    fn is_skipped(&self, service: &dyn Service) -> bool {
+        matches_any(&self.skip, service)
+    }
+}
+
+/// Returns the services to start automatically, in dependency order, and
+/// warns about the ones that are not.
+///
+/// Dependencies of selected services are started even if they are not
+/// selected themselves, unless they are explicitly skipped. [Lazy] services
+/// are only started as dependencies, unless `include_lazy` is set.
+///
+/// [Lazy]: crate::Service::lazy
+pub(crate) fn select(
+    graph: &Graph<'_>,
+    include_lazy: bool,
+) -> Result<Vec<usize>, Vec<ServiceError>> {
+    if flag(DISABLE_VAR) {
+        warn(format_args!(
+            "automatic startup of services disabled by {}",
+            DISABLE_VAR
+        ));
+        return Ok(Vec::new());
+    }
+
+    let selection = Selection::from_env();
+    let services: Vec<_> = graph.services().collect();
+    let roots = (0..services.len())
+        .filter(|&i| {
+            (include_lazy || !services[i].lazy())
+                && selection.is_selected(services[i])
+                && !selection.is_skipped(services[i])
+        })
+        .collect();
+    let mut included = graph.with_dependencies(roots);
+
+    for (i, service) in services.iter().enumerate() {
+        let reason = if selection.is_skipped(*service) {
+            included[i] = false;
+            Reason::Skipped
+        } else if included[i] || service.lazy() {
+            continue;
+        } else {
+            Reason::NotSelected
+        };
+
+        warn(format_args!(
+            "skipping service `{}`: {}",
+            service.name(),
+            reason
+        ));
+    }
+
+    graph.filtered_order(&included)
+}
+
+/// Whether the variable is set to anything but `0` or the empty string.
+pub(crate) fn flag(var: &str) -> bool {
+    env::var_os(var).is_some_and(|value| value != "0" && !value.is_empty())
+}
+
+/// Parses a comma-separated list of patterns. Returns `None` if the variable
+/// is unset or empty.
+fn patterns(var: &str) -> Option<Vec<String>> {
+    let value = env::var(var).ok()?;
+    let patterns: Vec<_> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(String::from)
+        .collect();
+
+    if patterns.is_empty() {
+        None
+    } else {
+        Some(patterns)
+    }
+}
+
+fn matches_any(patterns: &[String], service: &dyn Service) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_prefix("tag:") {
+            Some(pattern) => {
+                service.tags().iter().any(|tag| glob(pattern, tag))
+            }
+            None => glob(pattern, service.name()),
+        })
+}
+
+/// Matches a name against a pattern where `*` matches any sequence of
+/// characters and `?` any single character.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let name: Vec<_> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    n = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,255 @@
+//! Services shared between processes, such as the test binaries of a single
+//! `cargo test` run.
+//!
+//! Each shared service has a state file in the state directory listing the
+//! PID of the service, if any, and the processes currently using it. The
+//! file is protected by an advisory lock. The first process to start the
+//! service actually starts it, later processes attach to it, and the last
+//! process to stop it actually stops it. Users that died without detaching
+//! are pruned, and a service left without live users is considered stale and
+//! killed before being started again.
+
+use crate::{janitor, state_dir, Service, ServiceError};
+use std::{
+    fmt::{self, Write},
+    fs::{self, File, OpenOptions},
+    io,
+    path::PathBuf,
+};
+
+/// Starts the service, or attaches to it if another live process already
+/// started it.
+pub(crate) fn start(service: &dyn Service) -> Result<(), ServiceError> {
+    let name = service.name();
+    let lock = Lock::acquire(name)
+        .map_err(|error| ServiceError::start(name, error))?;
+    let mut state = lock.read();
+    state.prune();
+
+    if state.users.is_empty() {
+        if let Some(pid) = state.pid.take() {
+            if janitor::is_recorded_process(pid, state.start_time) {
+                janitor::kill(pid);
+            }
+        }
+
+        service.start()?;
+        state.set_pid(service.pid());
+    } else {
+        service.attach(state.pid)?;
+    }
+
+    state.users.push(Process::current());
+    lock.write(&state)
+        .map_err(|error| ServiceError::start(name, error))
+}
+
+/// Stops the service if no other live process uses it, or detaches from it
+/// otherwise.
+pub(crate) fn stop(service: &dyn Service) -> Result<(), ServiceError> {
+    let name = service.name();
+    let lock =
+        Lock::acquire(name).map_err(|error| ServiceError::stop(name, error))?;
+    let mut state = lock.read();
+    let current = Process::current();
+    state.users.retain(|user| *user != current);
+    state.prune();
+
+    if state.users.is_empty() {
+        // Another process may have restarted the service since this one
+        // started or attached to it
+        if service.pid() != state.pid {
+            service.detach()?;
+            service.attach(state.pid)?;
+        }
+        service.stop()?;
+        state.set_pid(None);
+    } else {
+        service.detach()?;
+    }
+
+    lock.write(&state)
+        .map_err(|error| ServiceError::stop(name, error))
+}
+
+/// Restarts the service on behalf of all processes using it.
+pub(crate) fn restart(service: &dyn Service) -> Result<(), ServiceError> {
+    let name = service.name();
+    let lock = Lock::acquire(name)
+        .map_err(|error| ServiceError::start(name, error))?;
+    let mut state = lock.read();
+    service.restart()?;
+    state.set_pid(service.pid());
+
+    lock.write(&state)
+        .map_err(|error| ServiceError::start(name, error))
+}
+
+/// A process, identified by its PID and start time.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Process {
+    pid: u32,
+    start_time: Option<u64>,
+}
+
+impl Process {
+    fn current() -> Self {
+        let pid = std::process::id();
+        Self {
+            pid,
+            start_time: janitor::start_time(pid),
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(2, ':');
+        Some(Self {
+            pid: parts.next()?.parse().ok()?,
+            start_time: parts.next().and_then(|time| time.parse().ok()),
+        })
+    }
+}
+
+impl fmt::Display for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pid)?;
+        if let Some(start_time) = self.start_time {
+            write!(f, ":{}", start_time)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    pid: Option<u32>,
+    start_time: Option<u64>,
+    users: Vec<Process>,
+}
+
+impl State {
+    fn parse(contents: &str) -> Self {
+        let mut state = State::default();
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("pid", value)) => state.pid = value.parse().ok(),
+                Some(("start_time", value)) => {
+                    state.start_time = value.parse().ok()
+                }
+                Some(("user", value)) => {
+                    state.users.extend(Process::parse(value))
+                }
+                _ => {}
+            }
+        }
+
+        state
+    }
+
+    fn set_pid(&mut self, pid: Option<u32>) {
+        self.pid = pid;
+        self.start_time = pid.and_then(janitor::start_time);
+    }
+
+    /// Removes users that died without detaching.
+    fn prune(&mut self) {
+        self.users
+            .retain(|user| janitor::is_alive(user.pid, user.start_time));
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(pid) = self.pid {
+            writeln!(f, "pid={}", pid)?;
+        }
+        if let Some(start_time) = self.start_time {
+            writeln!(f, "start_time={}", start_time)?;
+        }
+        for user in &self.users {
+            writeln!(f, "user={}", user)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Exclusive advisory lock over the state of a shared service. Released when
+/// dropped.
+struct Lock {
+    _file: File,
+    state_path: PathBuf,
+}
+
+impl Lock {
+    fn acquire(name: &str) -> io::Result<Self> {
+        let dir = state_dir().join("shared");
+        fs::create_dir_all(&dir)?;
+        let name = name.chars().fold(String::new(), |mut name, c| {
+            if c.is_alphanumeric() {
+                name.push(c);
+            } else {
+                // Keep distinct names distinct
+                let _ = write!(name, "_{:x}", c as u32);
+            }
+            name
+        });
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(format!("{}.lock", name)))?;
+        lock_file(&file)?;
+
+        Ok(Self {
+            _file: file,
+            state_path: dir.join(format!("{}.state", name)),
+        })
+    }
+
+    fn read(&self) -> State {
+        fs::read_to_string(&self.state_path)
+            .map(|contents| State::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    fn write(&self, state: &State) -> io::Result<()> {
+        if state.users.is_empty() && state.pid.is_none() {
+            match fs::remove_file(&self.state_path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    Err(error)
+                }
+                _ => Ok(()),
+            }
+        } else {
+            fs::write(&self.state_path, state.to_string())
+        }
+    }
+}
+
+/// Blocks until the file is exclusively locked.
+#[cfg(unix)]
+pub(crate) fn lock_file(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lock_file(_: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "shared services are only supported on Unix",
+    ))
+}
@@ -0,0 +1,76 @@
+//! Spawning of child processes that die along with the current process.
+//!
+//! On Linux this relies on `PR_SET_PDEATHSIG`. The death signal is delivered
+//! when the *thread* that spawned the child exits, not the whole process, so
+//! children are spawned from a dedicated thread that lives as long as the
+//! process does. Other platforms spawn children normally and rely on the
+//! janitor to clean up after crashed runs. The death signal does not reach
+//! the processes the child spawned in turn, which the janitor kills through
+//! the child's process group.
+
+use std::{
+    io,
+    process::{Child, Command},
+};
+
+/// Spawns the command so that the child is killed when the current process
+/// exits, however it exits.
+#[cfg(target_os = "linux")]
+pub(crate) fn spawn(mut command: Command) -> io::Result<Child> {
+    use std::{
+        os::unix::process::CommandExt,
+        sync::{
+            mpsc::{self, Sender},
+            Mutex, OnceLock,
+        },
+        thread,
+    };
+
+    type Request = (Command, Sender<io::Result<Child>>);
+
+    static SPAWNER: OnceLock<Mutex<Sender<Request>>> = OnceLock::new();
+
+    let parent = std::process::id() as libc::pid_t;
+    unsafe {
+        command.pre_exec(move || {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // The parent may have died before the death signal was set up
+            if libc::getppid() != parent {
+                return Err(io::Error::other("parent process exited"));
+            }
+
+            Ok(())
+        });
+    }
+
+    let spawner = SPAWNER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Request>();
+        thread::Builder::new()
+            .name("companion-service spawner".to_string())
+            .spawn(move || {
+                for (mut command, reply) in receiver {
+                    let _ = reply.send(command.spawn());
+                }
+            })
+            .expect("failed to start the spawner thread");
+
+        Mutex::new(sender)
+    });
+
+    let (reply, result) = mpsc::channel();
+    spawner
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .send((command, reply))
+        .map_err(|_| io::Error::other("spawner thread is gone"))?;
+    result
+        .recv()
+        .map_err(|_| io::Error::other("spawner thread is gone"))?
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn spawn(mut command: Command) -> io::Result<Child> {
+    command.spawn()
+}
@@ -0,0 +1,70 @@
+//! Status queries.
+
+use crate::registry::{self, Entry};
+use std::{fmt, time::Duration};
+
+/// Lifecycle state of a service, as tracked by this crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ServiceState {
+    /// Not running. This is the initial state.
+    Stopped,
+    /// Being started, or waiting to become ready.
+    Starting,
+    /// Started and ready.
+    Running,
+    /// Being stopped.
+    Stopping,
+    /// The last lifecycle operation failed.
+    Failed,
+}
+
+impl fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ServiceState::Stopped => "stopped",
+            ServiceState::Starting => "starting",
+            ServiceState::Running => "running",
+            ServiceState::Stopping => "stopping",
+            ServiceState::Failed => "failed",
+        })
+    }
+}
+
+/// Status of a registered service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Status {
+    /// Name of the service.
+    pub name: String,
+    /// Current state of the service.
+    pub state: ServiceState,
+    /// How long the last successful start took, including waiting for the
+    /// service to be ready.
+    pub start_duration: Option<Duration>,
+    /// How many times the service exited on its own while running. See
+    /// [`Service::exited`](crate::Service::exited).
+    pub crashes: u32,
+}
+
+impl Status {
+    fn of(entry: &Entry) -> Self {
+        Self {
+            name: entry.service().name().to_string(),
+            state: entry.state(),
+            start_duration: entry.start_duration(),
+            crashes: entry.crashes(),
+        }
+    }
+}
+
+/// Status of the service with the given name, or `None` if there is no such
+/// service. If multiple services share the name, the first one registered is
+/// returned.
+pub fn status(name: &str) -> Option<Status> {
+    registry::named(name).next().map(Status::of)
+}
+
+/// Status of all registered services, in registration order.
+pub fn statuses() -> Vec<Status> {
+    registry::entries().into_iter().map(Status::of).collect()
+}
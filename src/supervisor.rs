@@ -0,0 +1,144 @@
+//! Supervision of running services that may exit on their own.
+
+use crate::registry;
+use std::{
+    sync::Once,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often running services are checked for unexpected exits.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// When a service that exited on its own is restarted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RestartPolicy {
+    /// The service is never restarted.
+    Never,
+    /// The service is restarted if it exited with a failure.
+    OnFailure,
+    /// The service is restarted whenever it exits.
+    Always,
+}
+
+/// How a service that was running exited on its own. See
+/// [`Service::exited`](crate::Service::exited).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Exit {
+    /// The service exited successfully, such as with code 0.
+    Success,
+    /// The service exited with a failure or was killed.
+    Failure,
+}
+
+/// How a service is restarted after exiting on its own. Restarts are
+/// delayed by an exponential backoff, and given up after a maximum number of
+/// attempts, at which point the service is marked as
+/// [`Failed`](crate::ServiceState::Failed).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Supervision {
+    policy: RestartPolicy,
+    backoff: Duration,
+    max_backoff: Duration,
+    max_restarts: u32,
+}
+
+impl Supervision {
+    /// Creates a supervision with the given policy, waiting 100ms before
+    /// the first restart and doubling the delay up to 10s for up to 5
+    /// restarts.
+    pub fn new(policy: RestartPolicy) -> Self {
+        Self {
+            policy,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            max_restarts: 5,
+        }
+    }
+
+    /// Sets the delay before the first restart, which is doubled for each
+    /// further attempt.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the longest delay between restarts.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Sets how many times the service is restarted before giving up. The
+    /// count starts over whenever the service is started explicitly.
+    pub fn max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// The restart policy.
+    pub fn policy(&self) -> RestartPolicy {
+        self.policy
+    }
+
+    /// Whether the service should be restarted after exiting this way.
+    pub(crate) fn restarts_after(&self, exit: Exit) -> bool {
+        match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit == Exit::Failure,
+            RestartPolicy::Always => true,
+        }
+    }
+
+    /// Delay before the given restart attempt, counting from 1, or `None` if
+    /// the attempts are exhausted.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt > self.max_restarts {
+            return None;
+        }
+
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        Some(
+            self.backoff
+                .checked_mul(factor)
+                .map_or(self.max_backoff, |delay| delay.min(self.max_backoff)),
+        )
+    }
+}
+
+impl Default for Supervision {
+    /// Never restarts the service.
+    fn default() -> Self {
+        Self::new(RestartPolicy::Never)
+    }
+}
+
+/// Supervision state of a service.
+#[derive(Default)]
+pub(crate) struct Supervised {
+    /// How many times the service exited on its own.
+    pub(crate) crashes: u32,
+    /// Restart attempts since the service was last started explicitly.
+    pub(crate) attempts: u32,
+    /// When the next restart is due, if one is.
+    pub(crate) due: Option<Instant>,
+}
+
+/// Starts the supervisor thread if it is not running yet.
+pub(crate) fn spawn() {
+    static SPAWNED: Once = Once::new();
+
+    SPAWNED.call_once(|| {
+        thread::Builder::new()
+            .name("companion-service supervisor".to_string())
+            .spawn(|| loop {
+                thread::sleep(POLL_INTERVAL);
+                for entry in registry::entries() {
+                    entry.supervise();
+                }
+            })
+            .expect("failed to spawn the supervisor thread");
+    });
+}
@@ -0,0 +1,68 @@
+//! Support for the [`companion_test`](crate::companion_test) attribute.
+
+use crate::{ensure, exclusive, reset, shared, warn, Exclusive, Shared};
+use std::thread;
+
+/// Guard for the services of a test. Resets them when dropped, if asked to.
+pub struct TestServices {
+    services: &'static [&'static str],
+    reset: bool,
+    // Held while the test runs
+    _shared: Vec<Shared>,
+    _exclusive: Vec<Exclusive>,
+}
+
+impl Drop for TestServices {
+    fn drop(&mut self) {
+        if !self.reset {
+            return;
+        }
+
+        for name in self.services {
+            if let Err(error) = reset(name) {
+                // Do not hide the original failure of the test
+                if thread::panicking() {
+                    warn(error);
+                } else {
+                    panic!("failed to reset service `{}`: {}", name, error);
+                }
+            }
+        }
+    }
+}
+
+/// Makes sure the services are running and ready, failing the test if any
+/// is not. Tests that reset their services get exclusive access to them.
+pub fn prepare(services: &'static [&'static str], reset: bool) -> TestServices {
+    for name in services {
+        if let Err(error) = ensure(name) {
+            panic!(
+                "test needs service `{}`, which is unavailable: {}",
+                name, error
+            );
+        }
+    }
+
+    // Guards are always acquired in the same order so that concurrent tests
+    // cannot deadlock
+    let mut names = services.to_vec();
+    names.sort_unstable();
+    names.dedup();
+    let mut shared_access = Vec::new();
+    let mut exclusive_access = Vec::new();
+    for name in names {
+        // The service exists since it was just started
+        if reset {
+            exclusive_access.push(exclusive(name).unwrap());
+        } else {
+            shared_access.push(shared(name).unwrap());
+        }
+    }
+
+    TestServices {
+        services,
+        reset,
+        _shared: shared_access,
+        _exclusive: exclusive_access,
+    }
+}
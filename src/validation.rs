@@ -0,0 +1,196 @@
+//! Validation of the registered services.
+
+use crate::{
+    registry::{self, Entry},
+    DependencyError, Phase, ServiceError,
+};
+use std::{error::Error, fmt, panic};
+
+/// Where a service is registered in the source code. See
+/// [`Service::location`](crate::Service::location).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Location {
+    file: &'static str,
+    line: u32,
+}
+
+impl Location {
+    /// Creates a location from a file name and a line number, typically
+    /// given by [`file!`] and [`line!`].
+    pub const fn new(file: &'static str, line: u32) -> Self {
+        Self { file, line }
+    }
+
+    /// The file name.
+    pub fn file(&self) -> &'static str {
+        self.file
+    }
+
+    /// The line number.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+}
+
+impl From<&'static panic::Location<'static>> for Location {
+    fn from(location: &'static panic::Location<'static>) -> Self {
+        Self::new(location.file(), location.line())
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Error used as the source of a [`ServiceError`] when the registered
+/// services are invalid, in which case nothing is started before `main`, or
+/// when a service cannot be registered at runtime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegistrationError {
+    /// The name of the service is empty, or contains whitespace or control
+    /// characters.
+    InvalidName(Option<Location>),
+    /// Multiple services have the same name, and not all of them opted into
+    /// [`Service::multi_instance`](crate::Service::multi_instance). Their
+    /// locations are listed in registration order.
+    Duplicate(Vec<Option<Location>>),
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::InvalidName(location) => write!(
+                f,
+                "invalid name registered at {}, names cannot be empty or \
+                 contain whitespace or control characters",
+                Site(*location)
+            ),
+            RegistrationError::Duplicate(locations) => {
+                write!(f, "registered {} times, at ", locations.len())?;
+                for (i, location) in locations.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(if i + 1 == locations.len() {
+                            " and "
+                        } else {
+                            ", "
+                        })?;
+                    }
+                    write!(f, "{}", Site(*location))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for RegistrationError {}
+
+/// A possibly unknown location.
+struct Site(Option<Location>);
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(location) => write!(f, "{}", location),
+            None => f.write_str("an unknown location"),
+        }
+    }
+}
+
+/// Whether a service name is valid. Kept in sync with the `service`
+/// attribute, which checks names at compile time.
+fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Checks that all service names are valid and unique, unless all services
+/// sharing a name opt into being multiple instances.
+pub(crate) fn validate() -> Result<(), Vec<ServiceError>> {
+    let entries = registry::entries();
+    let mut errors = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        // Report each group once, from its first service
+        let name = entry.service().name();
+        let first = entries
+            .iter()
+            .position(|other| other.service().name() == name);
+        if first != Some(i) {
+            continue;
+        }
+
+        let group: Vec<_> = entries
+            .iter()
+            .copied()
+            .filter(|other| other.service().name() == name)
+            .collect();
+        errors.extend(check_group(&group, Phase::Start).err());
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Checks that a service can be registered along with the given ones, which
+/// must include all of its dependencies.
+pub(crate) fn check(
+    entries: &[&Entry],
+    entry: &Entry,
+) -> Result<(), ServiceError> {
+    let name = entry.service().name();
+    let mut group: Vec<_> = entries
+        .iter()
+        .copied()
+        .filter(|other| other.service().name() == name)
+        .collect();
+    group.push(entry);
+    check_group(&group, Phase::Register)?;
+
+    for dependency in entry.service().depends_on() {
+        if !entries
+            .iter()
+            .any(|other| other.service().name() == *dependency)
+        {
+            return Err(ServiceError::new(
+                name,
+                Phase::Register,
+                DependencyError::Missing(dependency.to_string()),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks a group of services sharing a name.
+fn check_group(group: &[&Entry], phase: Phase) -> Result<(), ServiceError> {
+    let name = group[0].service().name();
+    if !is_valid(name) {
+        return Err(ServiceError::new(
+            name,
+            phase,
+            RegistrationError::InvalidName(group[0].location()),
+        ));
+    }
+
+    if group.len() > 1
+        && !group.iter().all(|entry| entry.service().multi_instance())
+    {
+        return Err(ServiceError::new(
+            name,
+            phase,
+            RegistrationError::Duplicate(
+                group.iter().map(|entry| entry.location()).collect(),
+            ),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Timeouts for lifecycle operations, enforced by a watchdog thread.
+//!
+//! Operations that take longer than their timeout are reported. The service
+//! is then killed through [`Service::kill`] if it supports it, and given a
+//! short grace period to finish the operation. If it does not, the process
+//! exits with [`TIMEOUT_EXIT_CODE`], without running destructors since those
+//! would likely hang on the same service.
+
+use crate::{selection::flag, warn, Phase, Service};
+use std::{
+    env,
+    fmt::Display,
+    io::{self, Write},
+    sync::{Condvar, Mutex, MutexGuard, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Exit code of processes where a lifecycle operation timed out.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Global timeout for all operations, in seconds.
+const TIMEOUT_VAR: &str = "COMPANION_SERVICES_TIMEOUT";
+/// Disables killing services that time out when set to `0`.
+const KILL_VAR: &str = "COMPANION_SERVICES_TIMEOUT_KILL";
+
+/// How long a killed service has to finish the operation that timed out.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Timeouts for the lifecycle operations of a service. Operations without a
+/// timeout fall back to the global ones set through the environment, and
+/// are not limited if there are none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    start: Option<Duration>,
+    stop: Option<Duration>,
+    restart: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Creates a set of timeouts where no operation has one.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timeout for starting the service, including waiting for it
+    /// to be ready.
+    pub fn start(mut self, timeout: Duration) -> Self {
+        self.start = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for stopping the service.
+    pub fn stop(mut self, timeout: Duration) -> Self {
+        self.stop = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for restarting or resetting the service, including
+    /// waiting for it to be ready.
+    pub fn restart(mut self, timeout: Duration) -> Self {
+        self.restart = Some(timeout);
+        self
+    }
+
+    /// The timeout for the given phase, if any.
+    pub fn get(&self, phase: Phase) -> Option<Duration> {
+        match phase {
+            Phase::Start => self.start,
+            Phase::Stop => self.stop,
+            Phase::Restart => self.restart,
+            _ => None,
+        }
+    }
+}
+
+/// Guard returned by [`watch`]. The operation is considered finished when it
+/// is dropped.
+pub(crate) struct Watch {
+    id: u64,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let watchdog = watchdog();
+        watchdog.lock().operations.retain(|op| op.id != self.id);
+        watchdog.changed.notify_all();
+    }
+}
+
+/// Watches an operation on the service until the returned guard is dropped.
+/// Returns `None` if the operation has no timeout.
+pub(crate) fn watch(
+    service: &'static (dyn Service + Sync),
+    phase: Phase,
+) -> Option<Watch> {
+    let timeout = service.timeouts().get(phase).or_else(|| global(phase))?;
+    let watchdog = watchdog();
+    let mut state = watchdog.lock();
+    state.next_id += 1;
+    let id = state.next_id;
+    state.operations.push(Operation {
+        id,
+        service,
+        phase,
+        timeout,
+        deadline: Instant::now() + timeout,
+        killed: false,
+    });
+    watchdog.changed.notify_all();
+
+    Some(Watch { id })
+}
+
+/// The global timeout for the given phase, from the environment. Each phase
+/// can be set through e.g. `COMPANION_SERVICES_STOP_TIMEOUT`, falling back
+/// to `COMPANION_SERVICES_TIMEOUT`.
+fn global(phase: Phase) -> Option<Duration> {
+    let var = format!(
+        "COMPANION_SERVICES_{}_TIMEOUT",
+        phase.to_string().to_uppercase()
+    );
+    [var.as_str(), TIMEOUT_VAR].iter().find_map(|var| {
+        let value = env::var(var).ok()?;
+        match value
+            .parse()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        {
+            Some(timeout) => Some(timeout),
+            None => {
+                warn(format_args!("ignoring invalid {}: `{}`", var, value));
+                None
+            }
+        }
+    })
+}
+
+struct Operation {
+    id: u64,
+    service: &'static (dyn Service + Sync),
+    phase: Phase,
+    timeout: Duration,
+    deadline: Instant,
+    killed: bool,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    operations: Vec<Operation>,
+}
+
+struct Watchdog {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Watchdog {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            let now = Instant::now();
+            if let Some(op) =
+                state.operations.iter_mut().find(|op| op.deadline <= now)
+            {
+                self.expire(op);
+                continue;
+            }
+
+            state = match state.operations.iter().map(|op| op.deadline).min() {
+                Some(deadline) => {
+                    self.changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|error| error.into_inner())
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|error| error.into_inner()),
+            };
+        }
+    }
+
+    fn expire(&self, op: &mut Operation) {
+        let name = op.service.name();
+        if op.killed {
+            alert(format_args!(
+                "service `{}` is still stuck after being killed, exiting",
+                name
+            ));
+            exit();
+        }
+
+        alert(format_args!(
+            "service `{}` did not {} within {:?}",
+            name, op.phase, op.timeout
+        ));
+        let kill = env::var_os(KILL_VAR).is_none() || flag(KILL_VAR);
+        if kill && op.service.kill() {
+            alert(format_args!("killed service `{}`", name));
+            op.killed = true;
+            op.deadline = Instant::now() + KILL_GRACE;
+        } else {
+            alert("exiting");
+            exit();
+        }
+    }
+}
+
+fn watchdog() -> &'static Watchdog {
+    static WATCHDOG: OnceLock<Watchdog> = OnceLock::new();
+
+    WATCHDOG.get_or_init(|| {
+        thread::Builder::new()
+            .name("companion-service watchdog".to_string())
+            .spawn(|| watchdog().run())
+            .expect("failed to spawn the watchdog thread");
+
+        Watchdog {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        }
+    })
+}
+
+/// Like [`warn`], but bypasses the output capturing of the test harness,
+/// which would otherwise swallow the message when the process exits.
+fn alert(message: impl Display) {
+    let _ = writeln!(io::stderr(), "companion-service: {}", message);
+}
+
+/// Exits immediately with [`TIMEOUT_EXIT_CODE`].
+#[cfg(unix)]
+fn exit() -> ! {
+    unsafe { libc::_exit(TIMEOUT_EXIT_CODE) }
+}
+
+#[cfg(not(unix))]
+fn exit() -> ! {
+    std::process::exit(TIMEOUT_EXIT_CODE)
+}
@@ -0,0 +1,110 @@
+use companion_service::{
+    companion_test, status, Service, ServiceError, ServiceState, SERVICES,
+};
+use linkme::distributed_slice;
+use std::{
+    env, fs,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// File where `Journal` logs its resets.
+const JOURNAL_VAR: &str = "COMPANION_TEST_JOURNAL";
+
+/// Counts how many times it was reset.
+struct Store {
+    resets: AtomicUsize,
+}
+
+impl Service for Store {
+    fn name(&self) -> &str {
+        "store"
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), ServiceError> {
+        self.resets.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+static STORE: Store = Store {
+    resets: AtomicUsize::new(0),
+};
+
+#[distributed_slice(SERVICES)]
+static STORE_SERVICE: &(dyn Service + Sync) = &STORE;
+
+/// Logs its resets to the file named by `COMPANION_TEST_JOURNAL`, so that
+/// they can be checked from another process.
+struct Journal;
+
+impl Service for Journal {
+    fn name(&self) -> &str {
+        "journal"
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), ServiceError> {
+        if let Some(path) = env::var_os(JOURNAL_VAR) {
+            fs::write(path, "reset\n").unwrap();
+        }
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static JOURNAL_SERVICE: &(dyn Service + Sync) = &Journal;
+
+#[companion_test(services = ["store"], reset)]
+fn uses_store() {
+    assert_eq!(status("store").unwrap().state, ServiceState::Running);
+}
+
+/// Run as a separate process by `resets_after_test`.
+#[companion_test(services = ["journal"], reset)]
+#[ignore]
+fn uses_journal() {}
+
+#[test]
+fn resets_after_test() {
+    let journal = env::temp_dir()
+        .join(format!("companion-test-journal-{}", std::process::id()));
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "uses_journal"])
+        .env(JOURNAL_VAR, &journal)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let log = fs::read_to_string(&journal).unwrap();
+    fs::remove_file(&journal).unwrap();
+    assert_eq!(log, "reset\n");
+}
+
+#[companion_test(services = ["missing"])]
+#[should_panic(expected = "test needs service `missing`")]
+fn missing_service() {}
@@ -0,0 +1,90 @@
+use companion_service::{Service, ServiceError, SERVICES};
+use linkme::distributed_slice;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+const SHARED_SERVICE_NAME: &str = "shared-service";
+
+/// Records the maximum number of lifecycle calls that overlapped.
+struct OverlapDetector {
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl OverlapDetector {
+    fn enter(&self) -> Result<(), ServiceError> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(1));
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Service for OverlapDetector {
+    fn name(&self) -> &str {
+        SHARED_SERVICE_NAME
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        self.enter()
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        self.enter()
+    }
+}
+
+static DETECTOR: OverlapDetector = OverlapDetector {
+    in_flight: AtomicUsize::new(0),
+    max_in_flight: AtomicUsize::new(0),
+};
+
+#[distributed_slice(SERVICES)]
+static DETECTOR_SERVICE: &(dyn Service + Sync) = &DETECTOR;
+
+#[test]
+fn serialized_lifecycle() {
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..10 {
+                    companion_service::restart(SHARED_SERVICE_NAME).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(DETECTOR.max_in_flight.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn exclusive_access() {
+    assert!(companion_service::exclusive("unknown").is_none());
+
+    let exclusive = companion_service::exclusive(SHARED_SERVICE_NAME).unwrap();
+    let acquired = Arc::new(AtomicBool::new(false));
+    let waiter = thread::spawn({
+        let acquired = acquired.clone();
+        move || {
+            let _shared =
+                companion_service::shared(SHARED_SERVICE_NAME).unwrap();
+            acquired.store(true, Ordering::SeqCst);
+        }
+    });
+
+    thread::sleep(Duration::from_millis(50));
+    assert!(!acquired.load(Ordering::SeqCst));
+    drop(exclusive);
+    waiter.join().unwrap();
+    assert!(acquired.load(Ordering::SeqCst));
+}
@@ -0,0 +1,208 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    reset, restart, start, stop, Service, ServiceError, SERVICES,
+    STARTUP_FAILURE_EXIT_CODE,
+};
+use linkme::distributed_slice;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const LOG_VAR: &str = "COMPANION_TEST_COORDINATOR_LOG";
+/// Makes the service fail to start when set.
+const FAIL_VAR: &str = "COMPANION_TEST_COORDINATOR_FAIL";
+
+/// Logs its lifecycle to the file named by `COMPANION_TEST_COORDINATOR_LOG`,
+/// if set.
+struct LoggingService;
+
+impl LoggingService {
+    fn log(&self, event: &str) {
+        if let Some(path) = env::var_os(LOG_VAR) {
+            let mut log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap();
+            writeln!(log, "{}", event).unwrap();
+        }
+    }
+}
+
+impl Service for LoggingService {
+    fn name(&self) -> &str {
+        "coordinated-logger"
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        self.log("start");
+        if env::var_os(FAIL_VAR).is_some() {
+            return Err(ServiceError::start(self.name(), "no license"));
+        }
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        self.log("stop");
+        Ok(())
+    }
+
+    fn attach(&self, _: Option<u32>) -> Result<(), ServiceError> {
+        self.log("attach");
+        Ok(())
+    }
+
+    fn detach(&self) -> Result<(), ServiceError> {
+        self.log("detach");
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static LOGGING_SERVICE: &(dyn Service + Sync) = &LoggingService;
+
+/// Run as a separate process by `coordinated_test_processes`.
+#[test]
+#[ignore]
+fn use_service() {
+    thread::sleep(Duration::from_millis(200));
+}
+
+#[test]
+#[cfg(unix)]
+fn coordinated_test_processes() {
+    let dir = env::temp_dir()
+        .join(format!("companion-coordinator-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("log");
+
+    // The shell plays the test runner, running one process per test
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(
+            "\"$0\" --ignored --exact use_service & \
+             \"$0\" --ignored --exact use_service & wait",
+        )
+        .arg(env::current_exe().unwrap())
+        .env("COMPANION_SERVICES_COORDINATOR", "1")
+        .env("COMPANION_SERVICES_STATE_DIR", &dir)
+        .env(LOG_VAR, &log)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The coordinator stops the service once the runner is gone
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let events = loop {
+        let events = fs::read_to_string(&log).unwrap_or_default();
+        if events.ends_with("stop\n") || Instant::now() > deadline {
+            break events;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    let mut events: Vec<_> = events.lines().collect();
+    assert_eq!(events.first(), Some(&"start"));
+    assert_eq!(events.last(), Some(&"stop"));
+    events.sort_unstable();
+    assert_eq!(
+        events,
+        ["attach", "attach", "detach", "detach", "start", "stop"]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Run as a separate process by `lifecycle_from_test_process`.
+#[test]
+#[ignore]
+fn cycle_service() {
+    stop("coordinated-logger").unwrap();
+    start("coordinated-logger").unwrap();
+    restart("coordinated-logger").unwrap();
+    reset("coordinated-logger").unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn lifecycle_from_test_process() {
+    let dir = env::temp_dir()
+        .join(format!("companion-coordinator-own-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("log");
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg("\"$0\" --ignored --exact cycle_service")
+        .arg(env::current_exe().unwrap())
+        .env("COMPANION_SERVICES_COORDINATOR", "1")
+        .env("COMPANION_SERVICES_STATE_DIR", &dir)
+        .env(LOG_VAR, &log)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The test process never started an instance of its own, and the
+    // coordinator restarted and reset its instance on its behalf
+    wait_for_coordinator(&dir);
+    let events = fs::read_to_string(&log).unwrap();
+    assert_eq!(
+        events.lines().collect::<Vec<_>>(),
+        [
+            "start", "attach", "detach", "attach", "stop", "start", "detach",
+            "attach", "stop", "start", "detach", "attach", "detach", "stop"
+        ]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Waits until the coordinator removed its directory, which it does last.
+fn wait_for_coordinator(dir: &Path) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::read_dir(dir.join("coordinators"))
+        .is_ok_and(|mut coordinators| coordinators.next().is_some())
+        && Instant::now() < deadline
+    {
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn coordinated_startup_failure() {
+    let dir = env::temp_dir()
+        .join(format!("companion-coordinator-fail-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("log");
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg("\"$0\" --ignored --exact use_service")
+        .arg(env::current_exe().unwrap())
+        .env("COMPANION_SERVICES_COORDINATOR", "1")
+        .env("COMPANION_SERVICES_STATE_DIR", &dir)
+        .env(LOG_VAR, &log)
+        .env(FAIL_VAR, "1")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(STARTUP_FAILURE_EXIT_CODE));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("failed to start service `coordinated-logger`: no license"));
+
+    // The coordinator rolled back, and nothing attached
+    wait_for_coordinator(&dir);
+    let events = fs::read_to_string(&log).unwrap();
+    assert_eq!(events.lines().collect::<Vec<_>>(), ["start", "stop"]);
+    fs::remove_dir_all(dir).unwrap();
+}
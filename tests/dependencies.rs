@@ -0,0 +1,146 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    Service, ServiceError, SERVICES, STARTUP_FAILURE_EXIT_CODE,
+};
+use linkme::distributed_slice;
+use std::{env, process::Command, sync::Mutex};
+
+/// Breaks the dependencies of the services below in the process run by
+/// `rejects_broken_dependencies`, since they abort the whole run. Either
+/// `cycle` or `missing`.
+const BROKEN_VAR: &str = "DEPENDENCIES_BROKEN";
+
+static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct LoggingService {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+}
+
+impl Service for LoggingService {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        EVENTS.lock().unwrap().push(format!("start {}", self.name));
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        EVENTS.lock().unwrap().push(format!("stop {}", self.name));
+        Ok(())
+    }
+}
+
+// Registered before its dependency on purpose
+#[distributed_slice(SERVICES)]
+static BROKER: &(dyn Service + Sync) = &LoggingService {
+    name: "broker",
+    depends_on: &["db"],
+};
+
+#[distributed_slice(SERVICES)]
+static DB: &(dyn Service + Sync) = &LoggingService {
+    name: "db",
+    depends_on: &[],
+};
+
+/// Depends on the other one in a cycle, or on an unknown service, when
+/// broken.
+struct Switchable {
+    name: &'static str,
+    other: &'static [&'static str],
+}
+
+impl Service for Switchable {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        match env::var(BROKEN_VAR).as_deref() {
+            Ok("cycle") => self.other,
+            Ok("missing") => &["ghost"],
+            _ => &[],
+        }
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static CACHE: &(dyn Service + Sync) = &Switchable {
+    name: "cache",
+    other: &["queue"],
+};
+
+#[distributed_slice(SERVICES)]
+static QUEUE: &(dyn Service + Sync) = &Switchable {
+    name: "queue",
+    other: &["cache"],
+};
+
+fn take_events() -> Vec<String> {
+    std::mem::take(&mut *EVENTS.lock().unwrap())
+}
+
+#[test]
+fn test() {
+    let startup = take_events();
+    assert!(
+        startup.iter().position(|event| event == "start db")
+            < startup.iter().position(|event| event == "start broker")
+    );
+
+    companion_service::stop_with_dependents("db").unwrap();
+    assert_eq!(take_events(), ["stop broker", "stop db"]);
+    companion_service::start_with_dependencies("broker").unwrap();
+    assert_eq!(take_events(), ["start db", "start broker"]);
+}
+
+#[test]
+#[ignore]
+fn nothing() {}
+
+/// Runs `nothing` with broken dependencies, returning the startup report.
+fn run_broken(broken: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "nothing"])
+        .env(BROKEN_VAR, broken)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(STARTUP_FAILURE_EXIT_CODE));
+    assert!(output.stdout.is_empty());
+
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn rejects_broken_dependencies() {
+    let stderr = run_broken("cycle");
+    assert!(stderr.contains(
+        "failed to start service `cache`: dependency cycle: cache -> queue \
+         -> cache"
+    ));
+
+    let stderr = run_broken("missing");
+    assert!(stderr.contains(
+        "failed to start service `cache`: depends on unknown service `ghost`"
+    ));
+    assert!(stderr.contains(
+        "failed to start service `queue`: depends on unknown service `ghost`"
+    ));
+}
@@ -0,0 +1,51 @@
+use companion_service::{Phase, Service, ServiceError, ServiceState, SERVICES};
+use linkme::distributed_slice;
+
+const FAILING_SERVICE_NAME: &str = "failing-service";
+
+struct FailingService;
+
+impl Service for FailingService {
+    fn name(&self) -> &str {
+        FAILING_SERVICE_NAME
+    }
+
+    // Failing before `main` would abort the whole run
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Err(ServiceError::start(self.name(), "initdb failed"))
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static FAILING_SERVICE: &(dyn Service + Sync) = &FailingService;
+
+#[test]
+fn start_error() {
+    let error = companion_service::start(FAILING_SERVICE_NAME).unwrap_err();
+    assert_eq!(error.name(), FAILING_SERVICE_NAME);
+    assert_eq!(error.phase(), Phase::Start);
+    assert_eq!(
+        error.to_string(),
+        "failed to start service `failing-service`: initdb failed"
+    );
+}
+
+#[test]
+fn restart_error() {
+    let error = companion_service::restart(FAILING_SERVICE_NAME).unwrap_err();
+    assert_eq!(error.phase(), Phase::Start);
+    assert_eq!(
+        companion_service::status(FAILING_SERVICE_NAME)
+            .unwrap()
+            .state,
+        ServiceState::Failed
+    );
+}
@@ -0,0 +1,106 @@
+use companion_service::{
+    restart_group, start, start_group, status, stop_group, Service,
+    ServiceError, ServiceState, SERVICES,
+};
+use linkme::distributed_slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Dummy {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+    tags: &'static [&'static str],
+    starts: AtomicUsize,
+}
+
+impl Service for Dummy {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn tags(&self) -> &[&str] {
+        self.tags
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+static POSTGRES: Dummy = Dummy {
+    name: "postgres",
+    depends_on: &[],
+    tags: &["storage"],
+    starts: AtomicUsize::new(0),
+};
+
+static REDIS: Dummy = Dummy {
+    name: "redis",
+    depends_on: &["network"],
+    tags: &["storage", "cache"],
+    starts: AtomicUsize::new(0),
+};
+
+static NETWORK: Dummy = Dummy {
+    name: "network",
+    depends_on: &[],
+    tags: &[],
+    starts: AtomicUsize::new(0),
+};
+
+static API: Dummy = Dummy {
+    name: "api",
+    depends_on: &["postgres"],
+    tags: &[],
+    starts: AtomicUsize::new(0),
+};
+
+#[distributed_slice(SERVICES)]
+static POSTGRES_SERVICE: &(dyn Service + Sync) = &POSTGRES;
+
+#[distributed_slice(SERVICES)]
+static REDIS_SERVICE: &(dyn Service + Sync) = &REDIS;
+
+#[distributed_slice(SERVICES)]
+static NETWORK_SERVICE: &(dyn Service + Sync) = &NETWORK;
+
+#[distributed_slice(SERVICES)]
+static API_SERVICE: &(dyn Service + Sync) = &API;
+
+fn state(name: &str) -> ServiceState {
+    status(name).unwrap().state
+}
+
+#[test]
+fn groups() {
+    // Dependencies are started along with the group
+    start_group("storage").unwrap();
+    assert_eq!(state("postgres"), ServiceState::Running);
+    assert_eq!(state("redis"), ServiceState::Running);
+    assert_eq!(state("network"), ServiceState::Running);
+    assert_eq!(state("api"), ServiceState::Stopped);
+
+    restart_group("cache").unwrap();
+    assert_eq!(REDIS.starts.load(Ordering::SeqCst), 2);
+    assert_eq!(POSTGRES.starts.load(Ordering::SeqCst), 1);
+
+    // Dependents are stopped along with the group
+    start("api").unwrap();
+    stop_group("storage").unwrap();
+    assert_eq!(state("postgres"), ServiceState::Stopped);
+    assert_eq!(state("redis"), ServiceState::Stopped);
+    assert_eq!(state("api"), ServiceState::Stopped);
+    assert_eq!(state("network"), ServiceState::Running);
+}
@@ -0,0 +1,186 @@
+use companion_service::{
+    clean_stale_instances, ensure, LazyService, Probe, ProcessService,
+    Readiness, Service, StaleInstance, SERVICES,
+};
+use linkme::distributed_slice;
+use regex::Regex;
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// File where `wrapper` writes the PID of the process it spawned.
+const WRAPPED_PID_VAR: &str = "COMPANION_TEST_WRAPPED_PID";
+
+fn wrapped_pid_file() -> PathBuf {
+    env::var_os(WRAPPED_PID_VAR).unwrap_or_default().into()
+}
+
+/// Runs its actual server in the background, like `pg_ctl` does.
+static WRAPPER: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("wrapper", "sh")
+        .arg("-c")
+        .arg(format!(
+            "sleep 300 & echo $! > {}; wait",
+            wrapped_pid_file().display()
+        ))
+        .readiness(Readiness::new().probe(Probe::log_line(
+            wrapped_pid_file(),
+            Regex::new(r"^\d+$").unwrap(),
+        )))
+        .lazy(true)
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static WRAPPER_SERVICE: &(dyn Service + Sync) = &WRAPPER;
+
+/// Start time of a process, as recorded by the janitor.
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> String {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+    let (_, fields) = stat.rsplit_once(')').unwrap();
+    fields.split_whitespace().nth(19).unwrap().to_string()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn cleans_stale_instances() {
+    let state_dir = env::temp_dir()
+        .join(format!("companion-janitor-{}", std::process::id()));
+    let instances = state_dir.join("instances");
+    let data_dir = state_dir.join("data");
+    fs::create_dir_all(&instances).unwrap();
+    fs::create_dir_all(&data_dir).unwrap();
+    env::set_var("COMPANION_SERVICES_STATE_DIR", &state_dir);
+
+    let mut dead_owner = Command::new("true").spawn().unwrap();
+    dead_owner.wait().unwrap();
+    let mut orphan = Command::new("sleep").arg("30").spawn().unwrap();
+    fs::write(
+        instances.join("orphan.state"),
+        format!(
+            "name=db\npid={}\nstart_time={}\nowner={}\ndata_dir={}\n",
+            orphan.id(),
+            start_time(orphan.id()),
+            dead_owner.id(),
+            data_dir.display()
+        ),
+    )
+    .unwrap();
+    fs::write(
+        instances.join("owned.state"),
+        format!("name=cache\npid=1\nowner={}\n", std::process::id()),
+    )
+    .unwrap();
+
+    // Without a start time, the PID may belong to an unrelated process
+    let mut unverified = Command::new("sleep").arg("30").spawn().unwrap();
+    fs::write(
+        instances.join("unverified.state"),
+        format!(
+            "name=queue\npid={}\nowner={}\n",
+            unverified.id(),
+            dead_owner.id()
+        ),
+    )
+    .unwrap();
+
+    let mut removed = companion_service::clean_stale_instances().unwrap();
+    removed.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(removed.len(), 2);
+    let StaleInstance {
+        name,
+        pid,
+        killed,
+        data_dirs,
+        ..
+    } = &removed[0];
+    assert_eq!(name, "db");
+    assert_eq!(*pid, orphan.id());
+    assert!(killed);
+    assert_eq!(data_dirs, std::slice::from_ref(&data_dir));
+
+    assert!(!orphan.wait().unwrap().success());
+    assert!(!data_dir.exists());
+    assert!(!instances.join("orphan.state").exists());
+    assert!(instances.join("owned.state").exists());
+
+    assert_eq!(removed[1].name, "queue");
+    assert!(!removed[1].killed);
+    assert!(unverified.try_wait().unwrap().is_none());
+    unverified.kill().unwrap();
+    unverified.wait().unwrap();
+
+    fs::remove_dir_all(state_dir).unwrap();
+}
+
+/// Run as a separate process by `kills_orphaned_group_members`, which kills
+/// it while the service is running.
+#[test]
+#[ignore]
+fn hold_wrapper() {
+    ensure("wrapper").unwrap();
+    thread::sleep(Duration::from_secs(60));
+}
+
+/// Run as a separate process by `kills_orphaned_group_members`. The
+/// automatic startup already cleans up, unless the `manual` feature is on.
+#[test]
+#[ignore]
+fn clean_up() {
+    clean_stale_instances().unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn kills_orphaned_group_members() {
+    let state_dir = env::temp_dir()
+        .join(format!("companion-janitor-group-{}", std::process::id()));
+    fs::create_dir_all(&state_dir).unwrap();
+    let pid_file = state_dir.join("wrapped.pid");
+    let run = |test| {
+        let mut command = Command::new(env::current_exe().unwrap());
+        command
+            .args(["--ignored", "--exact", test])
+            .env("COMPANION_SERVICES_STATE_DIR", &state_dir)
+            .env(WRAPPED_PID_VAR, &pid_file)
+            .stdout(Stdio::null());
+        command
+    };
+
+    let mut owner = run("hold_wrapper").spawn().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let wrapped: u32 = loop {
+        let pid = fs::read_to_string(&pid_file).unwrap_or_default();
+        if let Ok(pid) = pid.trim().parse() {
+            break pid;
+        }
+        assert!(Instant::now() < deadline, "the wrapper did not start");
+        thread::sleep(Duration::from_millis(10));
+    };
+    owner.kill().unwrap();
+    owner.wait().unwrap();
+    assert!(is_alive(wrapped));
+
+    assert!(run("clean_up").status().unwrap().success());
+    // Signals are delivered asynchronously
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while is_alive(wrapped) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!is_alive(wrapped));
+    fs::remove_dir_all(state_dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+fn is_alive(pid: u32) -> bool {
+    // Zombies are dead, but may never be reaped inside containers
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => !stat.rsplit(')').next().unwrap().starts_with(" Z"),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,48 @@
+use companion_service::{
+    launch, start_all, status, stop_all, Service, ServiceError, ServiceState,
+    SERVICES,
+};
+use linkme::distributed_slice;
+
+struct Dummy;
+
+impl Service for Dummy {
+    fn name(&self) -> &str {
+        "dummy"
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static DUMMY: &(dyn Service + Sync) = &Dummy;
+
+fn state() -> ServiceState {
+    status("dummy").unwrap().state
+}
+
+#[test]
+fn explicit_lifecycle() {
+    // Started automatically unless the `manual` feature is enabled
+    stop_all().unwrap();
+    assert_eq!(state(), ServiceState::Stopped);
+
+    start_all().unwrap();
+    assert_eq!(state(), ServiceState::Running);
+    stop_all().unwrap();
+    assert_eq!(state(), ServiceState::Stopped);
+
+    let companions = launch().unwrap();
+    assert_eq!(state(), ServiceState::Running);
+    drop(companions);
+    assert_eq!(state(), ServiceState::Stopped);
+
+    launch().unwrap().stop().unwrap();
+    assert_eq!(state(), ServiceState::Stopped);
+}
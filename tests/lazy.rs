@@ -0,0 +1,87 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    ensure, status, Service, ServiceError, ServiceState, UnknownService,
+    SERVICES,
+};
+use linkme::distributed_slice;
+use std::error::Error;
+
+struct Dummy {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+    lazy: bool,
+}
+
+impl Service for Dummy {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn lazy(&self) -> bool {
+        self.lazy
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static SEARCH: &(dyn Service + Sync) = &Dummy {
+    name: "search",
+    depends_on: &["index"],
+    lazy: true,
+};
+
+#[distributed_slice(SERVICES)]
+static INDEX: &(dyn Service + Sync) = &Dummy {
+    name: "index",
+    depends_on: &[],
+    lazy: true,
+};
+
+#[distributed_slice(SERVICES)]
+static DB: &(dyn Service + Sync) = &Dummy {
+    name: "db",
+    depends_on: &[],
+    lazy: true,
+};
+
+#[distributed_slice(SERVICES)]
+static API: &(dyn Service + Sync) = &Dummy {
+    name: "api",
+    depends_on: &["db"],
+    lazy: false,
+};
+
+fn state(name: &str) -> ServiceState {
+    status(name).unwrap().state
+}
+
+#[test]
+fn start_on_first_use() {
+    // Lazy services only start before `main` as dependencies
+    assert_eq!(state("api"), ServiceState::Running);
+    assert_eq!(state("db"), ServiceState::Running);
+    assert_eq!(state("search"), ServiceState::Stopped);
+    assert_eq!(state("index"), ServiceState::Stopped);
+
+    ensure("search").unwrap();
+    assert_eq!(state("search"), ServiceState::Running);
+    assert_eq!(state("index"), ServiceState::Running);
+    ensure("search").unwrap();
+
+    let error = ensure("missing").unwrap_err();
+    assert_eq!(error.name(), "missing");
+    assert!(error.source().unwrap().is::<UnknownService>());
+}
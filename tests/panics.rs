@@ -0,0 +1,104 @@
+use companion_service::{
+    start, status, stop, PanicError, Phase, Service, ServiceError,
+    ServiceState, SERVICES,
+};
+use linkme::distributed_slice;
+use std::error::Error;
+
+/// Panics in the given phase.
+struct Panicking {
+    name: &'static str,
+    phase: Phase,
+}
+
+impl Service for Panicking {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        if self.phase == Phase::Start {
+            panic!("{} cannot start", self.name);
+        }
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        eprintln!("stopping {}", self.name);
+        if self.phase == Phase::Stop {
+            panic!("{} cannot stop", self.name);
+        }
+        Ok(())
+    }
+}
+
+// Registered first so that it is stopped last
+#[distributed_slice(SERVICES)]
+static GOOD: &(dyn Service + Sync) = &Panicking {
+    name: "good",
+    phase: Phase::Restart,
+};
+
+#[distributed_slice(SERVICES)]
+static BAD_START: &(dyn Service + Sync) = &Panicking {
+    name: "bad-start",
+    phase: Phase::Start,
+};
+
+#[distributed_slice(SERVICES)]
+static BAD_STOP: &(dyn Service + Sync) = &Panicking {
+    name: "bad-stop",
+    phase: Phase::Stop,
+};
+
+#[test]
+fn start_panic() {
+    let error = start("bad-start").unwrap_err();
+    assert_eq!(error.phase(), Phase::Start);
+    assert_eq!(
+        error.to_string(),
+        "failed to start service `bad-start`: panicked: bad-start cannot start"
+    );
+    let source = error.source().unwrap();
+    let source = source.downcast_ref::<PanicError>().unwrap();
+    assert_eq!(source.message(), "bad-start cannot start");
+    assert_eq!(status("bad-start").unwrap().state, ServiceState::Failed);
+}
+
+/// Run as a separate process by `shutdown_panic`.
+#[test]
+#[ignore]
+fn start_bad_stop() {
+    start("good").unwrap();
+    start("bad-stop").unwrap();
+}
+
+#[cfg(not(feature = "manual"))]
+#[test]
+fn shutdown_panic() {
+    use std::{env, process::Command};
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "start_bad_stop"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "failed to stop service `bad-stop`: panicked: bad-stop cannot stop"
+    ));
+    // Services stopped afterwards are still stopped
+    let panic = stderr.find("stopping bad-stop").unwrap();
+    assert!(stderr[panic..].contains("stopping good"));
+}
+
+#[test]
+fn stop_panic() {
+    start("bad-stop").unwrap();
+    let error = stop("bad-stop").unwrap_err();
+    assert_eq!(error.phase(), Phase::Stop);
+    assert_eq!(status("bad-stop").unwrap().state, ServiceState::Failed);
+}
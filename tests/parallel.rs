@@ -0,0 +1,85 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{status, Service, ServiceError, SERVICES};
+use linkme::distributed_slice;
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+const BOOT_TIME: Duration = Duration::from_millis(200);
+
+/// Takes a while to start, and remembers when it did.
+struct Slow {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+    started: Mutex<Option<(Instant, Instant)>>,
+}
+
+impl Slow {
+    const fn new(
+        name: &'static str,
+        depends_on: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            depends_on,
+            started: Mutex::new(None),
+        }
+    }
+
+    fn started(&self) -> (Instant, Instant) {
+        self.started.lock().unwrap().unwrap()
+    }
+}
+
+impl Service for Slow {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        let begin = Instant::now();
+        thread::sleep(BOOT_TIME);
+        *self.started.lock().unwrap() = Some((begin, Instant::now()));
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+static FIRST: Slow = Slow::new("first", &[]);
+static SECOND: Slow = Slow::new("second", &[]);
+static DEPENDENT: Slow = Slow::new("dependent", &["first", "second"]);
+
+#[distributed_slice(SERVICES)]
+static FIRST_SERVICE: &(dyn Service + Sync) = &FIRST;
+
+#[distributed_slice(SERVICES)]
+static SECOND_SERVICE: &(dyn Service + Sync) = &SECOND;
+
+#[distributed_slice(SERVICES)]
+static DEPENDENT_SERVICE: &(dyn Service + Sync) = &DEPENDENT;
+
+#[test]
+fn starts_independent_services_concurrently() {
+    let (first_begin, first_end) = FIRST.started();
+    let (second_begin, second_end) = SECOND.started();
+    let (dependent_begin, _) = DEPENDENT.started();
+
+    // Independent services overlap
+    assert!(first_begin < second_end && second_begin < first_end);
+    // Dependents wait for all of their dependencies
+    assert!(dependent_begin >= first_end && dependent_begin >= second_end);
+
+    let duration = status("dependent").unwrap().start_duration.unwrap();
+    assert!(duration >= BOOT_TIME);
+}
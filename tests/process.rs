@@ -0,0 +1,173 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    Exit, Input, LazyService, Probe, ProcessService, Readiness, Service,
+    StopOutcome, SERVICES,
+};
+use linkme::distributed_slice;
+use regex::Regex;
+use std::{
+    env, fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+const SLEEPER_NAME: &str = "sleeper";
+
+fn log_file() -> PathBuf {
+    env::temp_dir()
+        .join(format!("companion-process-{}.log", std::process::id()))
+}
+
+fn pid_file() -> PathBuf {
+    env::temp_dir()
+        .join(format!("companion-process-{}.pid", std::process::id()))
+}
+
+static SLEEPER: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder(SLEEPER_NAME, "sleep")
+        .arg("30")
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static SLEEPER_SERVICE: &(dyn Service + Sync) = &SLEEPER;
+
+static ECHO: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("echo", "cat")
+        .stdin(Input::Bytes(b"hello from stdin\n".to_vec()))
+        .log_file(log_file())
+        .readiness(Readiness::new().probe(Probe::log_line(
+            log_file(),
+            Regex::new("^hello from").unwrap(),
+        )))
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static ECHO_SERVICE: &(dyn Service + Sync) = &ECHO;
+
+static ORPHAN_CANDIDATE: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("orphan-candidate", "sleep")
+        .arg("30")
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static ORPHAN_CANDIDATE_SERVICE: &(dyn Service + Sync) = &ORPHAN_CANDIDATE;
+
+/// Ignores the stop signal and leaves a grandchild behind.
+static STUBBORN: LazyService<ProcessService> =
+    LazyService::new(|| {
+        ProcessService::builder("stubborn", "sh")
+            .arg("-c")
+            .arg(format!(
+                "trap '' TERM; sleep 30 & echo $! > {}; wait",
+                pid_file().display()
+            ))
+            .readiness(Readiness::new().probe(Probe::log_line(
+                pid_file(),
+                Regex::new(r"^\d+$").unwrap(),
+            )))
+            .stop_timeout(Duration::from_millis(100))
+            .build()
+    });
+
+#[distributed_slice(SERVICES)]
+static STUBBORN_SERVICE: &(dyn Service + Sync) = &STUBBORN;
+
+fn is_alive(pid: u32) -> bool {
+    // Zombies are dead, but may never be reaped inside containers
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => !stat.rsplit(')').next().unwrap().starts_with(" Z"),
+        Err(_) => false,
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn lifecycle() {
+    let pid = SLEEPER.pid().unwrap();
+    assert!(is_alive(pid));
+
+    companion_service::stop(SLEEPER_NAME).unwrap();
+    assert_eq!(SLEEPER.pid(), None);
+    assert!(!is_alive(pid));
+    assert_eq!(SLEEPER.last_stop_outcome(), Some(StopOutcome::Clean));
+
+    companion_service::start(SLEEPER_NAME).unwrap();
+    assert!(is_alive(SLEEPER.pid().unwrap()));
+}
+
+#[test]
+fn stdin_and_log_file() {
+    // Readiness only passes once the input was echoed to the log
+    assert!(fs::read_to_string(log_file())
+        .unwrap()
+        .contains("hello from stdin"));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn kill_process_group() {
+    let grandchild = fs::read_to_string(pid_file()).unwrap();
+    let grandchild = grandchild.trim().parse().unwrap();
+    assert!(is_alive(grandchild));
+
+    assert_eq!(STUBBORN.terminate().unwrap(), Some(StopOutcome::Killed));
+    assert!(!is_alive(grandchild));
+    assert_eq!(STUBBORN.terminate().unwrap(), None);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn outlives_starting_thread() {
+    ORPHAN_CANDIDATE.stop().unwrap();
+    thread::spawn(|| ORPHAN_CANDIDATE.start().unwrap())
+        .join()
+        .unwrap();
+
+    // The death signal would be delivered right after the thread exits
+    thread::sleep(Duration::from_millis(50));
+    assert!(is_alive(ORPHAN_CANDIDATE.pid().unwrap()));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn kills_group_of_exited_leader() {
+    let pid_file = env::temp_dir()
+        .join(format!("companion-process-{}.deserter", std::process::id()));
+    // Not registered, so that the supervisor leaves it alone
+    let deserter = ProcessService::builder("deserter", "sh")
+        .arg("-c")
+        .arg(format!(
+            "sleep 30 & echo $! > {}; exit 3",
+            pid_file.display()
+        ))
+        .build();
+    deserter.start().unwrap();
+    let leader = deserter.pid().unwrap();
+    while deserter.exited().is_none() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(deserter.exited(), Some(Exit::Failure));
+
+    // The leader is not reaped yet, so its process group cannot be reused
+    assert!(fs::metadata(format!("/proc/{}", leader)).is_ok());
+    let grandchild = fs::read_to_string(&pid_file).unwrap();
+    let grandchild = grandchild.trim().parse().unwrap();
+    assert!(is_alive(grandchild));
+
+    let outcome = deserter.terminate().unwrap();
+    assert!(matches!(outcome, Some(StopOutcome::Exited(_))));
+    // Signals are delivered asynchronously
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while is_alive(grandchild) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!is_alive(grandchild));
+    assert!(fs::metadata(format!("/proc/{}", leader)).is_err());
+    fs::remove_file(pid_file).unwrap();
+}
@@ -0,0 +1,127 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    Probe, Readiness, ReadinessError, Service, ServiceError, SERVICES,
+};
+use linkme::distributed_slice;
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+const SLOW_SERVICE_NAME: &str = "slow-service";
+const NEVER_READY_SERVICE_NAME: &str = "never-ready-service";
+
+fn marker() -> PathBuf {
+    env::temp_dir().join(format!("companion-readiness-{}", std::process::id()))
+}
+
+/// Creates its marker file some time after being started.
+struct SlowService;
+
+impl Service for SlowService {
+    fn name(&self) -> &str {
+        SLOW_SERVICE_NAME
+    }
+
+    fn readiness(&self) -> Readiness {
+        Readiness::new()
+            .probe(Probe::file(marker()))
+            .interval(Duration::from_millis(10))
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        thread::spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            fs::write(marker(), "").unwrap();
+        });
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        let _ = fs::remove_file(marker());
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static SLOW_SERVICE: &(dyn Service + Sync) = &SlowService;
+
+struct NeverReadyService;
+
+impl Service for NeverReadyService {
+    fn name(&self) -> &str {
+        NEVER_READY_SERVICE_NAME
+    }
+
+    // Failing before `main` would abort the whole run
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn readiness(&self) -> Readiness {
+        Readiness::new()
+            .probe(Probe::file("/nonexistent/companion-service"))
+            .interval(Duration::from_millis(10))
+            .timeout(Duration::from_millis(50))
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static NEVER_READY_SERVICE: &(dyn Service + Sync) = &NeverReadyService;
+
+#[test]
+fn waits_for_readiness() {
+    assert!(marker().exists());
+}
+
+#[test]
+fn readiness_timeout() {
+    let error = companion_service::start(NEVER_READY_SERVICE_NAME).unwrap_err();
+    let source = error.source().unwrap();
+    let source = source.downcast_ref::<ReadinessError>().unwrap();
+    assert_eq!(source.probe(), "file /nonexistent/companion-service");
+}
+
+#[test]
+fn http_probe() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/health", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = stream.write_all(b"HTTP/1.0 204 No Content\r\n\r\n");
+        }
+    });
+
+    assert!(Probe::http_status(&url, 204).check().is_ok());
+    assert!(Probe::http(&url).check().is_err());
+}
+
+#[test]
+fn command_probe_timeout() {
+    let readiness = Readiness::new()
+        .probe(Probe::command("sleep", ["30"]))
+        .timeout(Duration::from_millis(100));
+
+    let started = Instant::now();
+    let error = readiness.wait().unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(error.probe(), "command `sleep 30`");
+}
@@ -0,0 +1,104 @@
+use companion_service::{
+    ensure, register, start_with_dependencies, state_dir, status, stop,
+    unregister, Phase, ProcessService, RegistrationError, Service,
+    ServiceError, ServiceState, UnknownService,
+};
+use std::{error::Error, fs};
+
+struct Dummy {
+    name: String,
+    depends_on: &'static [&'static str],
+}
+
+impl Dummy {
+    fn boxed(
+        name: &str,
+        depends_on: &'static [&'static str],
+    ) -> Box<dyn Service + Send + Sync> {
+        Box::new(Self {
+            name: name.to_string(),
+            depends_on,
+        })
+    }
+}
+
+impl Service for Dummy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+fn state(name: &str) -> Option<ServiceState> {
+    status(name).map(|status| status.state)
+}
+
+#[test]
+fn runtime_registration() {
+    register(Dummy::boxed("runtime-db", &[])).unwrap();
+    register(Dummy::boxed("runtime-api", &["runtime-db"])).unwrap();
+    assert_eq!(state("runtime-api"), Some(ServiceState::Stopped));
+
+    start_with_dependencies("runtime-api").unwrap();
+    assert_eq!(state("runtime-api"), Some(ServiceState::Running));
+    assert_eq!(state("runtime-db"), Some(ServiceState::Running));
+
+    let error = register(Dummy::boxed("runtime-db", &[])).unwrap_err();
+    assert_eq!(error.phase(), Phase::Register);
+    let source = error.source().unwrap().downcast_ref();
+    assert!(matches!(source, Some(RegistrationError::Duplicate(_))));
+
+    let error = register(Dummy::boxed("bad name", &[])).unwrap_err();
+    let source = error.source().unwrap().downcast_ref();
+    assert!(matches!(source, Some(RegistrationError::InvalidName(_))));
+
+    unregister("runtime-api").unwrap();
+    assert_eq!(state("runtime-api"), None);
+    let error = unregister("runtime-api").unwrap_err();
+    assert!(error.source().unwrap().is::<UnknownService>());
+}
+
+/// Janitor records of the service owned by the current process.
+fn records(name: &str) -> usize {
+    let prefix = format!("{}-{}-", name, std::process::id());
+    fs::read_dir(state_dir().join("instances"))
+        .map(|entries| {
+            entries
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    let name = name.to_string_lossy();
+                    name.starts_with(&prefix) && name.ends_with(".state")
+                })
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+#[test]
+fn records_every_instance() {
+    for _ in 0..2 {
+        let service = ProcessService::builder("sleepers", "sleep")
+            .arg("30")
+            .multi_instance(true)
+            .build();
+        register(Box::new(service)).unwrap();
+    }
+
+    ensure("sleepers").unwrap();
+    assert_eq!(records("sleepers"), 2);
+
+    stop("sleepers").unwrap();
+    assert_eq!(records("sleepers"), 0);
+    unregister("sleepers").unwrap();
+}
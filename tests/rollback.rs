@@ -0,0 +1,121 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    status, Service, ServiceError, ServiceState, SERVICES,
+    STARTUP_FAILURE_EXIT_CODE,
+};
+use linkme::distributed_slice;
+use std::{env, process::Command};
+
+/// Makes `third` fail to start in the process run by `rolls_back_startup`,
+/// since that aborts the whole run.
+const FAIL_VAR: &str = "ROLLBACK_FAIL";
+
+/// Each link depends on the previous one, and reports being stopped.
+struct Link {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+}
+
+impl Service for Link {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        if self.name == "third" && env::var_os(FAIL_VAR).is_some() {
+            Err(ServiceError::start(self.name, "out of disk space"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        eprintln!("stopped {}", self.name);
+        if self.name == "first" {
+            Err(ServiceError::stop(self.name, "still busy"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static FIRST: &(dyn Service + Sync) = &Link {
+    name: "first",
+    depends_on: &[],
+};
+
+#[distributed_slice(SERVICES)]
+static SECOND: &(dyn Service + Sync) = &Link {
+    name: "second",
+    depends_on: &["first"],
+};
+
+#[distributed_slice(SERVICES)]
+static THIRD: &(dyn Service + Sync) = &Link {
+    name: "third",
+    depends_on: &["second"],
+};
+
+#[distributed_slice(SERVICES)]
+static FOURTH: &(dyn Service + Sync) = &Link {
+    name: "fourth",
+    depends_on: &["third"],
+};
+
+#[distributed_slice(SERVICES)]
+static FIFTH: &(dyn Service + Sync) = &Link {
+    name: "fifth",
+    depends_on: &["fourth"],
+};
+
+/// Run as a separate process by `rolls_back_startup`.
+#[test]
+#[ignore]
+fn nothing() {}
+
+#[test]
+fn starts_all_links() {
+    assert_eq!(status("fifth").unwrap().state, ServiceState::Running);
+}
+
+#[test]
+fn rolls_back_startup() {
+    for sequential in ["0", "1"] {
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "nothing"])
+            .env(FAIL_VAR, "1")
+            .env("COMPANION_SERVICES_SEQUENTIAL", sequential)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(output.status.code(), Some(STARTUP_FAILURE_EXIT_CODE));
+        assert!(output.stdout.is_empty());
+
+        // Dependents of the failed service are never started, and the
+        // others are stopped in reverse order, once
+        let stopped: Vec<_> = stderr
+            .lines()
+            .filter_map(|line| line.strip_prefix("stopped "))
+            .collect();
+        assert_eq!(stopped, ["third", "second", "first"]);
+
+        // Errors from both starting and stopping are reported
+        assert!(stderr.contains("4 service(s) failed during startup"));
+        assert!(stderr
+            .contains("failed to start service `third`: out of disk space"));
+        assert!(stderr.contains(
+            "failed to start service `fourth`: dependency `third` failed"
+        ));
+        assert!(stderr.contains(
+            "failed to start service `fifth`: dependency `fourth` failed"
+        ));
+        assert!(stderr.contains("failed to stop service `first`: still busy"));
+    }
+}
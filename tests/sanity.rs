@@ -1,8 +1,11 @@
-use companion_service::{Service, SERVICES};
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{Service, ServiceError, ServiceState, SERVICES};
 use linkme::distributed_slice;
 use std::sync::atomic::{AtomicIsize, Ordering};
 
-const TEST_SERVICE_NAME: &str = "test service";
+const TEST_SERVICE_NAME: &str = "test-service";
 
 struct TestService {
     start_stop_count: AtomicIsize,
@@ -25,12 +28,14 @@ impl Service for TestService {
         TEST_SERVICE_NAME
     }
 
-    fn start(&self) {
+    fn start(&self) -> Result<(), ServiceError> {
         self.start_stop_count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
     }
 
-    fn stop(&self) {
+    fn stop(&self) -> Result<(), ServiceError> {
         self.start_stop_count.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
     }
 }
 
@@ -39,15 +44,32 @@ static TEST_SERVICE_IMPL: TestService = TestService::new();
 #[distributed_slice(SERVICES)]
 static TEST_SERVICE: &(dyn Service + Sync) = &TEST_SERVICE_IMPL;
 
+fn state() -> ServiceState {
+    companion_service::status(TEST_SERVICE_NAME).unwrap().state
+}
+
 #[test]
 fn test() {
     assert_eq!(TEST_SERVICE_IMPL.start_stop_count(), 1);
-    companion_service::stop(TEST_SERVICE_NAME);
+    assert_eq!(state(), ServiceState::Running);
+    companion_service::stop(TEST_SERVICE_NAME).unwrap();
     assert_eq!(TEST_SERVICE_IMPL.start_stop_count(), 0);
-    companion_service::start(TEST_SERVICE_NAME);
+    assert_eq!(state(), ServiceState::Stopped);
+    companion_service::stop(TEST_SERVICE_NAME).unwrap();
+    assert_eq!(TEST_SERVICE_IMPL.start_stop_count(), 0);
+    companion_service::start(TEST_SERVICE_NAME).unwrap();
+    assert_eq!(TEST_SERVICE_IMPL.start_stop_count(), 1);
+    assert_eq!(state(), ServiceState::Running);
+    companion_service::start(TEST_SERVICE_NAME).unwrap();
     assert_eq!(TEST_SERVICE_IMPL.start_stop_count(), 1);
-    companion_service::restart(TEST_SERVICE_NAME);
+    companion_service::restart(TEST_SERVICE_NAME).unwrap();
     assert_eq!(TEST_SERVICE_IMPL.start_stop_count(), 1);
+    assert_eq!(state(), ServiceState::Running);
+
+    assert!(companion_service::status("unknown").is_none());
+    let statuses = companion_service::statuses();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].name, TEST_SERVICE_NAME);
 
     // Unfortunately we can't test the destructor
 }
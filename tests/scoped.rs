@@ -0,0 +1,67 @@
+use companion_service::{
+    scoped, start, status, stop, Service, ServiceError, ServiceState,
+    UnknownService, SERVICES,
+};
+use linkme::distributed_slice;
+use std::{
+    error::Error,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Counts how many times it was started.
+struct Counting {
+    starts: AtomicUsize,
+}
+
+impl Service for Counting {
+    fn name(&self) -> &str {
+        "counting"
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+static COUNTING: Counting = Counting {
+    starts: AtomicUsize::new(0),
+};
+
+#[distributed_slice(SERVICES)]
+static COUNTING_SERVICE: &(dyn Service + Sync) = &COUNTING;
+
+fn state() -> ServiceState {
+    status("counting").unwrap().state
+}
+
+#[test]
+fn reference_counted() {
+    assert_eq!(state(), ServiceState::Stopped);
+
+    let outer = scoped("counting").unwrap();
+    assert_eq!(state(), ServiceState::Running);
+    let inner = scoped("counting").unwrap();
+    drop(inner);
+    assert_eq!(state(), ServiceState::Running);
+    drop(outer);
+    assert_eq!(state(), ServiceState::Stopped);
+    assert_eq!(COUNTING.starts.load(Ordering::SeqCst), 1);
+
+    // Services that were already running are left running
+    start("counting").unwrap();
+    drop(scoped("counting").unwrap());
+    assert_eq!(state(), ServiceState::Running);
+    stop("counting").unwrap();
+
+    let error = scoped("missing").err().unwrap();
+    assert!(error.source().unwrap().is::<UnknownService>());
+}
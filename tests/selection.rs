@@ -0,0 +1,129 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    statuses, Service, ServiceError, ServiceState, SERVICES,
+};
+use linkme::distributed_slice;
+use std::{env, process::Command};
+
+struct Dummy {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+    tags: &'static [&'static str],
+}
+
+impl Service for Dummy {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn tags(&self) -> &[&str] {
+        self.tags
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static DB: &(dyn Service + Sync) = &Dummy {
+    name: "db",
+    depends_on: &[],
+    tags: &[],
+};
+
+#[distributed_slice(SERVICES)]
+static APP: &(dyn Service + Sync) = &Dummy {
+    name: "app",
+    depends_on: &["db"],
+    tags: &[],
+};
+
+#[distributed_slice(SERVICES)]
+static CACHE: &(dyn Service + Sync) = &Dummy {
+    name: "cache",
+    depends_on: &[],
+    tags: &["memory"],
+};
+
+#[distributed_slice(SERVICES)]
+static BROKER_MAIN: &(dyn Service + Sync) = &Dummy {
+    name: "broker-main",
+    depends_on: &[],
+    tags: &[],
+};
+
+#[distributed_slice(SERVICES)]
+static BROKER_REPLICA: &(dyn Service + Sync) = &Dummy {
+    name: "broker-replica",
+    depends_on: &[],
+    tags: &[],
+};
+
+/// Run as a separate process by `selected_services`. Prints the running
+/// services.
+#[test]
+#[ignore]
+fn print_running() {
+    for status in statuses() {
+        if status.state == ServiceState::Running {
+            println!("running: {}", status.name);
+        }
+    }
+}
+
+/// Runs `print_running` in a process with the given environment, returning
+/// the sorted names of the running services and stderr.
+fn run(vars: &[(&str, &str)]) -> (Vec<String>, String) {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "print_running", "--nocapture"])
+        .envs(vars.iter().copied())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // The test harness may print on the same line
+    let mut running: Vec<_> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.split("running: ").nth(1))
+        .map(String::from)
+        .collect();
+    running.sort();
+    (running, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn selected_services() {
+    let (running, stderr) = run(&[
+        ("COMPANION_SERVICES", "app, broker-*"),
+        ("COMPANION_SERVICES_SKIP", "*-replica"),
+    ]);
+    assert_eq!(running, ["app", "broker-main", "db"]);
+    assert!(stderr.contains(
+        "skipping service `cache`: not selected by COMPANION_SERVICES"
+    ));
+    assert!(stderr.contains(
+        "skipping service `broker-replica`: skipped by COMPANION_SERVICES_SKIP"
+    ));
+
+    let (running, _) = run(&[("COMPANION_SERVICES_SKIP", "db")]);
+    assert_eq!(running, ["app", "broker-main", "broker-replica", "cache"]);
+
+    let (running, _) = run(&[("COMPANION_SERVICES", "tag:mem*")]);
+    assert_eq!(running, ["cache"]);
+
+    let (running, stderr) = run(&[("COMPANION_SERVICES_DISABLE", "1")]);
+    assert!(running.is_empty());
+    assert!(stderr.contains("disabled by COMPANION_SERVICES_DISABLE"));
+}
@@ -0,0 +1,63 @@
+use companion_service::{
+    ensure, service, status, ServiceError, ServiceState, SERVICES,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[service(name = "attribute-db")]
+struct Db;
+
+impl Db {
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+/// Constructed on first use, since it has fields.
+#[service(
+    name = "attribute-api",
+    depends_on = ["attribute-db"],
+    tags = ["web"],
+    lazy
+)]
+#[derive(Default)]
+struct Api {
+    starts: AtomicUsize,
+}
+
+impl Api {
+    fn start(&self) -> Result<(), ServiceError> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn registered() {
+    let registered: Vec<_> =
+        SERVICES.iter().map(|service| service.name()).collect();
+    assert!(registered.contains(&"attribute-db"));
+    assert!(registered.contains(&"attribute-api"));
+
+    let api = SERVICES
+        .iter()
+        .find(|service| service.name() == "attribute-api")
+        .unwrap();
+    assert_eq!(api.depends_on(), ["attribute-db"]);
+    assert_eq!(api.tags(), ["web"]);
+    assert!(api.lazy());
+
+    ensure("attribute-api").unwrap();
+    assert_eq!(
+        status("attribute-api").unwrap().state,
+        ServiceState::Running
+    );
+    assert_eq!(status("attribute-db").unwrap().state, ServiceState::Running);
+}
@@ -0,0 +1,171 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    ensure, restart, LazyService, ProcessService, Service, ServiceError,
+    SERVICES,
+};
+use linkme::distributed_slice;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+const LOG_VAR: &str = "COMPANION_TEST_SHARED_LOG";
+/// File where `restart_service` writes the PID of the restarted process.
+const PID_VAR: &str = "COMPANION_TEST_SHARED_PID";
+
+/// Logs its lifecycle to the file named by `COMPANION_TEST_SHARED_LOG`, if
+/// set.
+struct LoggingService;
+
+impl LoggingService {
+    fn log(&self, event: &str) {
+        if let Some(path) = env::var_os(LOG_VAR) {
+            let mut log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap();
+            writeln!(log, "{}", event).unwrap();
+        }
+    }
+}
+
+impl Service for LoggingService {
+    fn name(&self) -> &str {
+        "shared-logger"
+    }
+
+    fn shared_between_processes(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        self.log("start");
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        self.log("stop");
+        Ok(())
+    }
+
+    fn attach(&self, _: Option<u32>) -> Result<(), ServiceError> {
+        self.log("attach");
+        Ok(())
+    }
+
+    fn detach(&self) -> Result<(), ServiceError> {
+        self.log("detach");
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static LOGGING_SERVICE: &(dyn Service + Sync) = &LoggingService;
+
+static SLEEPER: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("shared-sleeper", "sleep")
+        .arg("30")
+        .shared(true)
+        .lazy(true)
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static SLEEPER_SERVICE: &(dyn Service + Sync) = &SLEEPER;
+
+/// Run as a separate process by `shared_between_binaries`.
+#[test]
+#[ignore]
+fn hold_service() {
+    thread::sleep(Duration::from_millis(300));
+}
+
+#[test]
+#[cfg(unix)]
+fn shared_between_binaries() {
+    let dir = env::temp_dir()
+        .join(format!("companion-shared-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("log");
+
+    let spawn = || {
+        Command::new(env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "hold_service"])
+            .env("COMPANION_SERVICES_STATE_DIR", &dir)
+            .env(LOG_VAR, &log)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap()
+    };
+    let mut first = spawn();
+    thread::sleep(Duration::from_millis(100));
+    let mut second = spawn();
+    assert!(first.wait().unwrap().success());
+    assert!(second.wait().unwrap().success());
+
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "start\nattach\ndetach\nstop\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Run as a separate process by `stops_process_restarted_elsewhere`.
+#[test]
+#[ignore]
+fn hold_sleeper() {
+    ensure("shared-sleeper").unwrap();
+    thread::sleep(Duration::from_millis(600));
+}
+
+/// Run as a separate process by `stops_process_restarted_elsewhere`.
+#[test]
+#[ignore]
+fn restart_sleeper() {
+    ensure("shared-sleeper").unwrap();
+    restart("shared-sleeper").unwrap();
+    let pid = SLEEPER.pid().unwrap();
+    fs::write(env::var_os(PID_VAR).unwrap(), pid.to_string()).unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn stops_process_restarted_elsewhere() {
+    let dir = env::temp_dir()
+        .join(format!("companion-shared-restart-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let pid_file = dir.join("pid");
+
+    let spawn = |test| {
+        Command::new(env::current_exe().unwrap())
+            .args(["--ignored", "--exact", test])
+            .env("COMPANION_SERVICES_STATE_DIR", &dir)
+            .env(PID_VAR, &pid_file)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap()
+    };
+    let mut holder = spawn("hold_sleeper");
+    thread::sleep(Duration::from_millis(200));
+    let mut restarter = spawn("restart_sleeper");
+    assert!(restarter.wait().unwrap().success());
+    assert!(holder.wait().unwrap().success());
+
+    // The holder was the last user, and stopped the restarted process
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+    // Zombies are dead, but may never be reaped inside containers
+    assert!(stat.map_or(true, |stat| stat
+        .rsplit(')')
+        .next()
+        .unwrap()
+        .starts_with(" Z")));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,194 @@
+use companion_service::{
+    start, status, Exit, LazyService, ProcessService, RestartPolicy, Service,
+    ServiceError, ServiceState, Status, Supervision, SERVICES,
+};
+use linkme::distributed_slice;
+use std::{
+    env, fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+fn marker() -> PathBuf {
+    env::temp_dir().join(format!("companion-supervisor-{}", std::process::id()))
+}
+
+fn on_failure() -> Supervision {
+    Supervision::new(RestartPolicy::OnFailure)
+        .backoff(Duration::from_millis(10))
+        .max_restarts(2)
+}
+
+/// Crashes the first time it is started, and stays up afterwards.
+static FLAKY: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("flaky", "sh")
+        .arg("-c")
+        .arg(format!(
+            "test -e {0} && exec sleep 30; touch {0}; exit 1",
+            marker().display()
+        ))
+        .lazy(true)
+        .supervision(on_failure())
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static FLAKY_SERVICE: &(dyn Service + Sync) = &FLAKY;
+
+static BROKEN: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("broken", "false")
+        .lazy(true)
+        .supervision(on_failure())
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static BROKEN_SERVICE: &(dyn Service + Sync) = &BROKEN;
+
+static FINISHED: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("finished", "true")
+        .lazy(true)
+        .supervision(on_failure())
+        .build()
+});
+
+#[distributed_slice(SERVICES)]
+static FINISHED_SERVICE: &(dyn Service + Sync) = &FINISHED;
+
+/// Panics when checked for an exit.
+struct Nosy;
+
+impl Service for Nosy {
+    fn name(&self) -> &str {
+        "nosy"
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn exited(&self) -> Option<Exit> {
+        panic!("nosy cannot tell");
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static NOSY_SERVICE: &(dyn Service + Sync) = &Nosy;
+
+/// Takes a while to start.
+struct Sluggish;
+
+impl Service for Sluggish {
+    fn name(&self) -> &str {
+        "sluggish"
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        thread::sleep(Duration::from_secs(3));
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static SLUGGISH_SERVICE: &(dyn Service + Sync) = &Sluggish;
+
+/// Exits right away, for checking that the supervisor is still working.
+static BRIEF: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("brief", "true").lazy(true).build()
+});
+
+#[distributed_slice(SERVICES)]
+static BRIEF_SERVICE: &(dyn Service + Sync) = &BRIEF;
+
+/// Exits right away while `sluggish` is starting.
+static HASTY: LazyService<ProcessService> = LazyService::new(|| {
+    ProcessService::builder("hasty", "true").lazy(true).build()
+});
+
+#[distributed_slice(SERVICES)]
+static HASTY_SERVICE: &(dyn Service + Sync) = &HASTY;
+
+/// Waits until the status of the service satisfies `done`.
+fn wait_for(name: &str, done: impl Fn(&Status) -> bool) -> Status {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let status = status(name).unwrap();
+        if done(&status) {
+            return status;
+        }
+        assert!(Instant::now() < deadline, "gave up on {:?}", status);
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn restarts_crashed_services() {
+    let _ = fs::remove_file(marker());
+    start("flaky").unwrap();
+    wait_for("flaky", |status| status.crashes == 1);
+
+    let status =
+        wait_for("flaky", |status| status.state == ServiceState::Running);
+    assert_eq!(status.crashes, 1);
+    assert!(FLAKY.pid().is_some());
+    let _ = fs::remove_file(marker());
+}
+
+#[test]
+fn gives_up_after_max_restarts() {
+    start("broken").unwrap();
+    wait_for("broken", |status| {
+        status.crashes == 3 && status.state == ServiceState::Failed
+    });
+
+    // No further restarts are attempted
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(status("broken").unwrap().crashes, 3);
+}
+
+#[test]
+fn leaves_finished_services_stopped() {
+    start("finished").unwrap();
+    let status =
+        wait_for("finished", |status| status.state == ServiceState::Stopped);
+    assert_eq!(status.crashes, 1);
+}
+
+#[test]
+fn survives_panicking_exit_checks() {
+    start("nosy").unwrap();
+    wait_for("nosy", |status| status.state == ServiceState::Failed);
+
+    start("brief").unwrap();
+    wait_for("brief", |status| status.crashes == 1);
+}
+
+#[test]
+fn skips_busy_services() {
+    let sluggish = thread::spawn(|| start("sluggish").unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    start("hasty").unwrap();
+    wait_for("hasty", |status| status.crashes == 1);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    sluggish.join().unwrap();
+}
@@ -0,0 +1,71 @@
+use companion_service::{
+    register, start_with_dependencies, status, stop_all, unregister,
+    DependencyError, Phase, Service, ServiceError, ServiceState,
+};
+use std::error::Error;
+
+struct Dummy {
+    name: &'static str,
+    depends_on: &'static [&'static str],
+}
+
+impl Service for Dummy {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&str] {
+        self.depends_on
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+fn register_dummy(name: &'static str, depends_on: &'static [&'static str]) {
+    register(Box::new(Dummy { name, depends_on })).unwrap();
+}
+
+fn state(name: &str) -> Option<ServiceState> {
+    status(name).map(|status| status.state)
+}
+
+#[test]
+fn keeps_dependencies_resolvable() {
+    let error = register(Box::new(Dummy {
+        name: "app",
+        depends_on: &["db"],
+    }))
+    .unwrap_err();
+    assert_eq!(error.phase(), Phase::Register);
+    let source = error.source().unwrap().downcast_ref();
+    assert_eq!(source, Some(&DependencyError::Missing("db".to_string())));
+
+    register_dummy("db", &[]);
+    register_dummy("app", &["db"]);
+    register_dummy("other", &[]);
+    start_with_dependencies("app").unwrap();
+    start_with_dependencies("other").unwrap();
+
+    let error = unregister("db").unwrap_err();
+    assert_eq!(error.phase(), Phase::Unregister);
+    let source = error.source().unwrap().downcast_ref();
+    assert_eq!(
+        source,
+        Some(&DependencyError::Required(vec!["app".to_string()]))
+    );
+    assert_eq!(state("db"), Some(ServiceState::Running));
+
+    stop_all().unwrap();
+    for name in ["db", "app", "other"] {
+        assert_eq!(state(name), Some(ServiceState::Stopped));
+    }
+
+    unregister("app").unwrap();
+    unregister("db").unwrap();
+}
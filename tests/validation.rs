@@ -0,0 +1,129 @@
+// Relies on services being started before `main`
+#![cfg(not(feature = "manual"))]
+
+use companion_service::{
+    service, status, Location, Service, ServiceError, ServiceState, SERVICES,
+    STARTUP_FAILURE_EXIT_CODE,
+};
+use linkme::distributed_slice;
+use std::{env, process::Command};
+
+/// Makes the services below invalid in the process run by
+/// `rejects_invalid_registrations`, since invalid registrations abort the
+/// whole run.
+const INVALID_VAR: &str = "VALIDATION_INVALID";
+
+fn invalid() -> bool {
+    env::var_os(INVALID_VAR).is_some()
+}
+
+#[service(name = "db")]
+struct Db;
+
+/// Takes the name of `Db` when invalid.
+struct OtherDb;
+
+impl Service for OtherDb {
+    fn name(&self) -> &str {
+        if invalid() {
+            "db"
+        } else {
+            "other-db"
+        }
+    }
+
+    fn location(&self) -> Option<Location> {
+        Some(Location::new(file!(), line!()))
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static OTHER_DB: &(dyn Service + Sync) = &OtherDb;
+
+#[service(name = "worker", multi_instance)]
+struct Worker;
+
+#[service(name = "worker", multi_instance)]
+struct OtherWorker;
+
+macro_rules! noop_lifecycle {
+    ($($ty:ty),*) => {
+        $(
+            impl $ty {
+                fn start(&self) -> Result<(), ServiceError> {
+                    Ok(())
+                }
+
+                fn stop(&self) -> Result<(), ServiceError> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+noop_lifecycle!(Db, Worker, OtherWorker);
+
+/// Registered without the attribute, which would reject its name.
+struct Invalid;
+
+impl Service for Invalid {
+    fn name(&self) -> &str {
+        if invalid() {
+            "bad name"
+        } else {
+            "good-name"
+        }
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+}
+
+#[distributed_slice(SERVICES)]
+static INVALID: &(dyn Service + Sync) = &Invalid;
+
+/// Run as a separate process by `rejects_invalid_registrations`.
+#[test]
+#[ignore]
+fn nothing() {}
+
+#[test]
+fn accepts_multiple_instances() {
+    assert_eq!(status("worker").unwrap().state, ServiceState::Running);
+}
+
+#[test]
+fn rejects_invalid_registrations() {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "nothing"])
+        .env(INVALID_VAR, "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // Nothing is started, not even valid services
+    assert_eq!(output.status.code(), Some(STARTUP_FAILURE_EXIT_CODE));
+    assert!(output.stdout.is_empty());
+    assert!(stderr.contains("2 service(s) failed during startup"));
+    assert!(stderr.contains(
+        "failed to start service `db`: registered 2 times, at \
+         tests/validation.rs:20 and tests/validation.rs:36"
+    ));
+    assert!(stderr.contains(
+        "failed to start service `bad name`: invalid name registered at an \
+         unknown location"
+    ));
+}
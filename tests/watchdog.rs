@@ -0,0 +1,104 @@
+use companion_service::{
+    start, stop, Service, ServiceError, Timeouts, SERVICES, TIMEOUT_EXIT_CODE,
+};
+use linkme::distributed_slice;
+use std::{
+    env,
+    process::{Command, Output},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Never finishes stopping, unless killed if it can be.
+struct Hanging {
+    name: &'static str,
+    killable: bool,
+    killed: AtomicBool,
+}
+
+impl Hanging {
+    const fn new(name: &'static str, killable: bool) -> Self {
+        Self {
+            name,
+            killable,
+            killed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Service for Hanging {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        Timeouts::new().stop(TIMEOUT)
+    }
+
+    fn kill(&self) -> bool {
+        self.killed.store(true, Ordering::SeqCst);
+        self.killable
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        while !(self.killable && self.killed.load(Ordering::SeqCst)) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+}
+
+static STUCK: Hanging = Hanging::new("stuck", false);
+static KILLABLE: Hanging = Hanging::new("killable", true);
+
+#[distributed_slice(SERVICES)]
+static STUCK_SERVICE: &(dyn Service + Sync) = &STUCK;
+
+#[distributed_slice(SERVICES)]
+static KILLABLE_SERVICE: &(dyn Service + Sync) = &KILLABLE;
+
+/// Run as a separate process by the tests below.
+#[test]
+#[ignore]
+fn stop_hanging_service() {
+    let name = env::var("HANGING_SERVICE").unwrap();
+    start(&name).unwrap();
+    stop(&name).unwrap();
+}
+
+fn run(name: &str) -> Output {
+    Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "stop_hanging_service"])
+        .env("HANGING_SERVICE", name)
+        .env_remove("COMPANION_SERVICES_TIMEOUT_KILL")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn exits_when_stuck() {
+    let output = run("stuck");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(TIMEOUT_EXIT_CODE));
+    assert!(stderr.contains("service `stuck` did not stop within 200ms"));
+}
+
+#[test]
+fn kills_stuck_services() {
+    let output = run("killable");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("service `killable` did not stop within 200ms"));
+    assert!(stderr.contains("killed service `killable`"));
+}